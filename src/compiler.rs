@@ -1,22 +1,30 @@
 mod error;
 mod language_components;
 mod parser;
+mod source_map;
 
 use crate::opcode::Opcode;
+use crate::program::{Constant, ConstantPool, Program};
 use error::{Error, ErrorKind};
 use language_components::*;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-const KEYWORDS: [&'static str; 2] = ["let", "return"];
+pub use source_map::{SourceMap, Variable};
+
+const KEYWORDS: [&str; 10] = [
+    "let", "return", "if", "else", "while", "break", "continue", "fn", "as", "struct",
+];
 
 #[derive(Debug)]
 enum Operand {
-    Value(Value),
+    // Literal with its location, in case loading it fails
+    Value(Value, Span),
     Register(Register),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 struct Register {
     value: u8,
     data_type: DataType,
@@ -33,6 +41,77 @@ impl Register {
     }
 }
 
+#[derive(Debug)]
+struct LoopContext {
+    start_idx: usize,
+    break_jump_indices: Vec<usize>,
+}
+
+impl LoopContext {
+    fn new(start_idx: usize) -> LoopContext {
+        LoopContext {
+            start_idx,
+            break_jump_indices: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Function {
+    address: u32,
+    parameters: Vec<DataType>,
+    return_type: Option<DataType>,
+}
+
+#[derive(Clone, Debug)]
+struct Struct {
+    // Field names and types in declaration order, which is also their order in memory
+    fields: Vec<(String, DataType)>,
+}
+
+impl Struct {
+    fn field(&self, name: &str) -> Option<(u8, &DataType)> {
+        self.fields
+            .iter()
+            .position(|(field_name, _)| field_name == name)
+            .map(|idx| (idx as u8, &self.fields[idx].1))
+    }
+}
+
+// Location that an accessor of an assignment reads from and writes to
+enum Member {
+    Element(Register), // register holding the list index
+    Entry(Register),   // register holding the map key
+    Field(u8),
+}
+
+impl Member {
+    fn index_register(&self) -> Option<&Register> {
+        match self {
+            Member::Element(register) | Member::Entry(register) => Some(register),
+            Member::Field(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FunctionContext {
+    name: String,
+    return_type: Option<DataType>,
+    body_start: usize,
+    // Only anonymous functions may capture variables from the enclosing scope
+    is_closure: bool,
+    // Captured variables as (register in the enclosing scope, register in the function's window)
+    captures: Vec<(u8, u8)>,
+    enclosing_scopes: Vec<Scope>,
+    enclosing_register_stack: Vec<u8>,
+    enclosing_closed_variables: HashSet<String>,
+    enclosing_unassigned: HashSet<u8>,
+}
+
+// Variables declared directly inside a block
+type Scope = HashMap<String, Register>;
+
 #[derive(Debug)]
 pub struct Compiler {
     filename: String,
@@ -40,8 +119,32 @@ pub struct Compiler {
 
     register_stack: Vec<u8>,
     operand_stack: Vec<Operand>,
-    variables: HashMap<String, Register>,
+    // Lexical scopes of the function being compiled, innermost last
+    scopes: Vec<Scope>,
+    // Variables whose scope has ended, used to report a better error when they are referenced
+    closed_variables: HashSet<String>,
+    // Registers of declared variables which might not have been assigned a value yet
+    unassigned: HashSet<u8>,
+    loop_contexts: Vec<LoopContext>,
+    functions: HashMap<String, Function>,
+    structs: HashMap<String, Struct>,
+    // Functions currently being compiled, innermost last
+    function_contexts: Vec<FunctionContext>,
+    // Whether an int operand mixed with a float operand is implicitly converted to float
+    int_promotion: bool,
     bytecode: Vec<Opcode>,
+    // Source span of every instruction in bytecode, see record_spans
+    spans: Vec<Span>,
+    constants: ConstantPool,
+    // Every variable declared so far with the instructions during which it is in scope
+    variables: Vec<Variable>,
+    function_bodies: Vec<(usize, usize)>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Compiler {
@@ -53,23 +156,49 @@ impl Compiler {
             source_code: String::new(),
             register_stack,
             operand_stack: Vec::new(),
-            variables: HashMap::new(),
+            scopes: Vec::new(),
+            closed_variables: HashSet::new(),
+            unassigned: HashSet::new(),
+            loop_contexts: Vec::new(),
+            functions: HashMap::new(),
+            structs: HashMap::new(),
+            function_contexts: Vec::new(),
+            int_promotion: false,
             bytecode: Vec::new(),
+            spans: Vec::new(),
+            constants: ConstantPool::default(),
+            variables: Vec::new(),
+            function_bodies: Vec::new(),
         }
     }
 
+    pub fn set_int_promotion(&mut self, enabled: bool) {
+        self.int_promotion = enabled;
+    }
+
     fn reset(&mut self) {
         self.register_stack.clear();
         for i in (0..=255).rev() {
             self.register_stack.push(i);
         }
         self.operand_stack.clear();
-        self.variables.clear();
+        self.scopes.clear();
+        self.scopes.push(Scope::new());
+        self.closed_variables.clear();
+        self.unassigned.clear();
+        self.loop_contexts.clear();
+        self.functions.clear();
+        self.structs.clear();
+        self.function_contexts.clear();
         self.bytecode.clear();
+        self.spans.clear();
+        self.constants.clear();
+        self.variables.clear();
+        self.function_bodies.clear();
     }
 
-    pub fn compile(&mut self, source_code: &str, filename: &str) -> Result<Vec<Opcode>, String> {
-        let function_body = parser::parse(source_code)?;
+    pub fn compile(&mut self, source_code: &str, filename: &str) -> Result<Program, String> {
+        let function_body = parser::parse(source_code, filename)?;
 
         self.reset();
         self.source_code = source_code.to_owned();
@@ -82,226 +211,2054 @@ impl Compiler {
                 }
             }
         }
-        let result = std::mem::take(&mut self.bytecode);
-        Ok(result)
+        self.record_spans(Span::new(0, source_code.len()));
+        let source_map = SourceMap::new(
+            self.filename.clone(),
+            self.source_code.clone(),
+            std::mem::take(&mut self.spans),
+            std::mem::take(&mut self.variables),
+            std::mem::take(&mut self.function_bodies),
+        );
+        let instructions = std::mem::take(&mut self.bytecode);
+        Ok(Program::new(
+            instructions,
+            self.constants.take(),
+            source_map,
+        ))
+    }
+
+    // Attributes every instruction emitted since the last call to the given span.
+    // Called after each expression and statement, so instructions map to the innermost one.
+    #[inline]
+    fn record_spans(&mut self, span: Span) {
+        self.spans.resize(self.bytecode.len(), span);
+    }
+
+    // Records that the register holds the variable from now on, so a debugger can show it by name
+    fn track_variable(&mut self, name: &str, register: u8, depth: usize, start: usize) {
+        self.variables
+            .push(Variable::new(name.to_owned(), register, depth, start));
+    }
+
+    fn untrack_variable(&mut self, name: &str, depth: usize) {
+        let end = self.bytecode.len();
+        if let Some(variable) = self.variables.iter_mut().rev().find(|variable| {
+            variable.is_open() && variable.depth() == depth && variable.name() == name
+        }) {
+            variable.close(end);
+        }
     }
 
     fn compile_control_flow(&mut self, control_flow: &ControlFlow) -> Result<(), Error> {
         match control_flow {
             ControlFlow::WhileLoop(while_loop) => self.compile_while_loop(while_loop)?,
             ControlFlow::BasicBlock(basic_block) => self.compile_basic_block(basic_block)?,
+            ControlFlow::If(if_statement) => self.compile_if_statement(if_statement)?,
+            ControlFlow::FunctionDeclaration(function_declaration) => {
+                self.compile_function_declaration(function_declaration)?
+            }
+            ControlFlow::StructDeclaration(struct_declaration) => {
+                self.compile_struct_declaration(struct_declaration)?
+            }
         }
         Ok(())
     }
 
-    fn compile_while_loop(&mut self, while_loop: &WhileLoop) -> Result<(), Error> {
-        let start_idx = self.bytecode.len();
-        self.compile_expression(while_loop.condition(), None)?;
-        let result_register = self.get_register();
-        if result_register.data_type != DataType::Bool {
+    fn compile_struct_declaration(
+        &mut self,
+        struct_declaration: &StructDeclaration,
+    ) -> Result<(), Error> {
+        let identifier = struct_declaration.identifier();
+        let kind = if Self::is_keyword(identifier.name()) {
+            Some(ErrorKind::IdentifierIsKeyword)
+        } else if self.structs.contains_key(identifier.name()) {
+            Some(ErrorKind::IdentifierAlreadyDefined)
+        } else if struct_declaration.fields().len() > u8::MAX as usize {
+            // Field indices are encoded as a single byte in the instructions
+            Some(ErrorKind::TooManyFields(identifier.name().to_owned()))
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
             return Err(Error::new(
                 self.filename.clone(),
                 self.source_code.clone(),
-                ErrorKind::ArgumentInvalidType(
-                    DataType::Bool.typename(),
-                    result_register.data_type.typename(),
-                ),
-                while_loop.span(),
-                while_loop.condition().span(),
+                kind,
+                struct_declaration.span(),
+                identifier.span(),
             ));
         }
 
-        let conditional_jump_opcode_idx = self.bytecode.len();
-        // Placeholder to be replaced later when we know the actual index where the loop ends
+        let mut fields: Vec<(String, DataType)> = Vec::new();
+        for field in struct_declaration.fields() {
+            let field_name = field.identifier().name();
+            if fields.iter().any(|(name, _)| name == field_name) {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::DuplicateField(field_name.to_owned()),
+                    struct_declaration.span(),
+                    field.identifier().span(),
+                ));
+            }
+            fields.push((field_name.to_owned(), field.data_type().clone()));
+        }
+        // Registered before the field types are checked so that a struct can contain itself, e.g. in a list
+        self.structs
+            .insert(identifier.name().to_owned(), Struct { fields });
+        for field in struct_declaration.fields() {
+            self.check_type(field.data_type(), field.span(), field.span())?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn check_map_key_type(
+        &self,
+        data_type: &DataType,
+        context: Span,
+        error: Span,
+    ) -> Result<(), Error> {
+        match data_type {
+            DataType::Int | DataType::Str => Ok(()),
+            _ => Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::InvalidMapKey(data_type.typename()),
+                context,
+                error,
+            )),
+        }
+    }
+
+    // Checks that every struct named by the type has been declared and that map keys are valid
+    fn check_type(&self, data_type: &DataType, context: Span, error: Span) -> Result<(), Error> {
+        match data_type {
+            DataType::Struct(name) if !self.structs.contains_key(name) => Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::UnknownType(name.clone()),
+                context,
+                error,
+            )),
+            DataType::List(element_type) => self.check_type(element_type, context, error),
+            DataType::Map(key_type, value_type) => {
+                self.check_map_key_type(key_type, context, error)?;
+                self.check_type(value_type, context, error)
+            }
+            DataType::Function(parameters, return_type) => {
+                for parameter in parameters {
+                    self.check_type(parameter, context, error)?;
+                }
+                match return_type {
+                    Some(return_type) => self.check_type(return_type, context, error),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn compile_function_declaration(
+        &mut self,
+        function_declaration: &FunctionDeclaration,
+    ) -> Result<(), Error> {
+        let identifier = function_declaration.identifier();
+        if Self::is_keyword(identifier.name()) {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::IdentifierIsKeyword,
+                function_declaration.span(),
+                identifier.span(),
+            ));
+        }
+        if self.functions.contains_key(identifier.name()) {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::IdentifierAlreadyDefined,
+                function_declaration.span(),
+                identifier.span(),
+            ));
+        }
+
+        // The body is placed inline, so the code around it has to jump over it
+        let jump_opcode_idx = self.bytecode.len();
         self.bytecode.push(Opcode::Error);
 
-        for statement in while_loop.body().statements() {
-            self.compile_statement(statement)?;
+        let address = self.bytecode.len();
+        if address > u32::MAX as usize {
+            panic!("Function address cannot fit into u32");
         }
-        // We are inside the loop, we need to jump backwards to before the conditional expression.
-        // The +1 is needed because the program counter is expected to increment after each operation as well,
-        // including this jump, so in thought we have to be one operation ahead already.
-        let offset = self.bytecode.len() - start_idx + 1;
-        if offset > i16::MAX as usize {
-            panic!("Jump offset cannot fit into i16");
+        // Registered before compiling the body so that the function can call itself
+        self.functions.insert(
+            identifier.name().to_owned(),
+            Function {
+                address: address as u32,
+                parameters: function_declaration
+                    .parameters()
+                    .iter()
+                    .map(|parameter| parameter.data_type().clone())
+                    .collect(),
+                return_type: function_declaration.return_type().cloned(),
+            },
+        );
+
+        self.compile_function_body(
+            identifier.name(),
+            function_declaration.parameters(),
+            function_declaration.return_type(),
+            function_declaration.body(),
+            false,
+            function_declaration.span(),
+            identifier.span(),
+        )?;
+
+        let offset = Self::forward_jump_offset(jump_opcode_idx, self.bytecode.len());
+        self.bytecode[jump_opcode_idx] = Opcode::Jump(offset);
+
+        Ok(())
+    }
+
+    // Compiles the body of a function at the current position and returns the variables it captured
+    #[allow(clippy::too_many_arguments)]
+    fn compile_function_body(
+        &mut self,
+        name: &str,
+        parameters: &[Parameter],
+        return_type: Option<&DataType>,
+        body: &Block,
+        is_closure: bool,
+        context: Span,
+        error: Span,
+    ) -> Result<Vec<(u8, u8)>, Error> {
+        if let Some(return_type) = return_type {
+            self.check_type(return_type, context, error)?;
+        }
+        // Covers the jump over the body, which the caller emitted
+        self.record_spans(context);
+        // Each function call gets a fresh register window, so the body is compiled with its own registers
+        let mut register_stack: Vec<u8> = (0..=255).rev().collect();
+        let body_start = self.bytecode.len();
+        let depth = self.function_contexts.len() + 1;
+        let mut variables = Scope::new();
+        for parameter in parameters {
+            let parameter_name = parameter.identifier().name();
+            if Self::is_keyword(parameter_name) || variables.contains_key(parameter_name) {
+                let kind = if Self::is_keyword(parameter_name) {
+                    ErrorKind::IdentifierIsKeyword
+                } else {
+                    ErrorKind::IdentifierAlreadyDefined
+                };
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    kind,
+                    parameter.span(),
+                    parameter.identifier().span(),
+                ));
+            }
+            self.check_type(parameter.data_type(), parameter.span(), parameter.span())?;
+            // Arguments are placed into the first registers of the window in order
+            let register = register_stack.pop().unwrap();
+            self.track_variable(parameter_name, register, depth, body_start);
+            variables.insert(
+                parameter_name.to_owned(),
+                Register::new(register, parameter.data_type().clone(), false),
+            );
         }
-        let offset = offset as i16 * -1;
-        self.bytecode.push(Opcode::Jump(offset));
 
-        // jump to after the loop is over in case the conditional expression evaluates to 'false'
-        // the -1 is needed because the program counter is expected to increment after each operation as well
-        let offset = self.bytecode.len() - conditional_jump_opcode_idx - 1;
-        if offset > i16::MAX as usize {
-            panic!("Jump offset cannot fit into i16");
+        std::mem::swap(&mut self.register_stack, &mut register_stack);
+        let scopes = std::mem::replace(&mut self.scopes, vec![variables]);
+        let closed_variables = std::mem::take(&mut self.closed_variables);
+        let unassigned = std::mem::take(&mut self.unassigned);
+        let loop_contexts = std::mem::take(&mut self.loop_contexts);
+        self.function_contexts.push(FunctionContext {
+            name: name.to_owned(),
+            return_type: return_type.cloned(),
+            body_start,
+            is_closure,
+            captures: Vec::new(),
+            enclosing_scopes: scopes,
+            enclosing_register_stack: register_stack,
+            enclosing_closed_variables: closed_variables,
+            enclosing_unassigned: unassigned,
+        });
+
+        let body_result = self.compile_block(body);
+
+        let function_context = self.function_contexts.pop().unwrap();
+        self.register_stack = function_context.enclosing_register_stack;
+        self.scopes = function_context.enclosing_scopes;
+        self.closed_variables = function_context.enclosing_closed_variables;
+        self.unassigned = function_context.enclosing_unassigned;
+        self.loop_contexts = loop_contexts;
+        body_result?;
+
+        match return_type {
+            Some(return_type) => {
+                if !Self::always_returns(body.control_flow_structures()) {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::MissingReturn(name.to_owned(), return_type.typename()),
+                        context,
+                        error,
+                    ));
+                }
+            }
+            None => self.bytecode.push(Opcode::ReturnVoid),
         }
-        self.bytecode[conditional_jump_opcode_idx] =
-            Opcode::JumpCond(result_register.value, offset as i16);
+        self.record_spans(context);
+        // Parameters and captures stay in scope until the end of the body
+        let body_end = self.bytecode.len();
+        for variable in &mut self.variables {
+            if variable.is_open() && variable.depth() == depth {
+                variable.close(body_end);
+            }
+        }
+        self.function_bodies.push((body_start, body_end));
+
+        Ok(function_context.captures)
+    }
+
+    fn compile_lambda(
+        &mut self,
+        lambda: &Lambda,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        // The body is placed inline, so the code around it has to jump over it
+        let jump_opcode_idx = self.bytecode.len();
+        self.bytecode.push(Opcode::Error);
+
+        let address = self.bytecode.len();
+        if address > u32::MAX as usize {
+            panic!("Function address cannot fit into u32");
+        }
+        let captures = self.compile_function_body(
+            "lambda",
+            lambda.parameters(),
+            lambda.return_type(),
+            lambda.body(),
+            true,
+            lambda.span(),
+            lambda.span(),
+        )?;
 
+        let offset = Self::forward_jump_offset(jump_opcode_idx, self.bytecode.len());
+        self.bytecode[jump_opcode_idx] = Opcode::Jump(offset);
+
+        // Captured values are read after the closure is created, so it must not overwrite any of them
+        let closure_register = match target_register {
+            Some(reg) if !captures.iter().any(|(src, _)| *src == reg) => reg,
+            _ => self.register_stack.pop().expect("Ran out of registers"),
+        };
+        self.bytecode
+            .push(Opcode::Closure(closure_register, address as u32));
+        for (src, slot) in captures {
+            self.bytecode
+                .push(Opcode::Capture(closure_register, src, slot));
+        }
+        let result_register = match target_register {
+            Some(reg) if reg != closure_register => {
+                self.bytecode.push(Opcode::Copy(closure_register, reg));
+                self.register_stack.push(closure_register);
+                reg
+            }
+            _ => closure_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            lambda.data_type(),
+            true,
+        )));
         Ok(())
     }
 
-    fn compile_basic_block(&mut self, basic_block: &BasicBlock) -> Result<(), Error> {
-        for statement in basic_block.statements() {
-            self.compile_statement(statement)?
+    fn compile_cast(&mut self, cast: &Cast, target_register: Option<u8>) -> Result<(), Error> {
+        self.check_type(cast.data_type(), cast.span(), cast.type_span())?;
+        self.compile_expression(cast.operand(), None)?;
+        let register = self.get_register()?;
+        let target_register = match target_register {
+            Some(reg) => reg,
+            None => {
+                if register.is_temporary {
+                    register.value
+                } else {
+                    self.register_stack.pop().expect("Ran out of registers")
+                }
+            }
+        };
+
+        let opcode = match (&register.data_type, cast.data_type()) {
+            (from, to) if from == to => Opcode::Copy(register.value, target_register),
+            (DataType::Int, DataType::Float) => Opcode::IntToFloat(register.value, target_register),
+            (DataType::Float, DataType::Int) => Opcode::FloatToInt(register.value, target_register),
+            (DataType::Int, DataType::Char) => Opcode::IntToChar(register.value, target_register),
+            (DataType::Char, DataType::Int) => Opcode::CharToInt(register.value, target_register),
+            (DataType::Int | DataType::Float | DataType::Bool | DataType::Char, DataType::Str) => {
+                Opcode::ToStr(register.value, target_register)
+            }
+            (from, to) => {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::InvalidCast(from.typename(), to.typename()),
+                    cast.span(),
+                    cast.type_span(),
+                ));
+            }
+        };
+        // Casting to the same type is a no-op unless the value has to be moved
+        if !matches!(opcode, Opcode::Copy(src, dst) if src == dst) {
+            self.bytecode.push(opcode);
+        }
+        self.operand_stack.push(Operand::Register(Register::new(
+            target_register,
+            cast.data_type().clone(),
+            true,
+        )));
+
+        if register.is_temporary && register.value != target_register {
+            self.register_stack.push(register.value);
         }
         Ok(())
     }
 
-    fn compile_statement(&mut self, statement: &Statement) -> Result<(), Error> {
-        match statement {
-            Statement::LetStatement(let_statement) => self.compile_let_statement(let_statement),
-            Statement::Assignment(assignemnt) => self.compile_assignment(assignemnt),
-            Statement::ReturnStatement(return_statement) => {
-                self.compile_return_statement(return_statement)
+    // Every embedded value is converted to a string and appended to the text before it
+    fn compile_interpolation(
+        &mut self,
+        interpolation: &Interpolation,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        // The embedded expressions might read the target register, so the string is built in a new one
+        let string_register = self.register_stack.pop().expect("Ran out of registers");
+        let (initial_text, parts) = match interpolation.parts().split_first() {
+            Some((InterpolationPart::Text(text), parts)) => (text.clone(), parts),
+            _ => (String::new(), interpolation.parts().as_slice()),
+        };
+        self.compile_load_value(
+            string_register,
+            Value::Str(Box::new(initial_text)),
+            interpolation.span(),
+        )?;
+
+        for part in parts {
+            let expression = match part {
+                InterpolationPart::Text(text) => {
+                    let text_register = self.register_stack.pop().expect("Ran out of registers");
+                    self.compile_load_value(
+                        text_register,
+                        Value::Str(Box::new(text.clone())),
+                        interpolation.span(),
+                    )?;
+                    self.bytecode
+                        .push(Opcode::AppendStr(string_register, text_register));
+                    self.register_stack.push(text_register);
+                    continue;
+                }
+                InterpolationPart::Expression(expression) => expression,
+            };
+            self.compile_expression(expression, None)?;
+            let register = self.get_register()?;
+            let (value_register, is_temporary) = if register.data_type == DataType::Str {
+                (register.value, register.is_temporary)
+            } else {
+                let converted_register = if register.is_temporary {
+                    register.value
+                } else {
+                    self.register_stack.pop().expect("Ran out of registers")
+                };
+                self.bytecode
+                    .push(Opcode::ToStr(register.value, converted_register));
+                (converted_register, true)
+            };
+            self.bytecode
+                .push(Opcode::AppendStr(string_register, value_register));
+            if is_temporary {
+                self.register_stack.push(value_register);
             }
-            Statement::Expression(expression) => {
-                self.compile_expression(expression, None)?;
+        }
 
-                // Discard the value of standalone expressions and release the register holding it
-                let result = self.get_register();
-                self.register_stack.push(result.value);
-                Ok(())
+        let result_register = match target_register {
+            Some(reg) => {
+                self.bytecode.push(Opcode::Copy(string_register, reg));
+                self.register_stack.push(string_register);
+                reg
+            }
+            None => string_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            DataType::Str,
+            true,
+        )));
+        Ok(())
+    }
+
+    // Like compile_expression, but values without a type of their own, like an empty list,
+    // take the expected type
+    fn compile_expression_as(
+        &mut self,
+        expression: &Expression,
+        expected_type: &DataType,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        match (expression, expected_type) {
+            (Expression::List(list), DataType::List(element_type)) => {
+                self.compile_list(list, Some(element_type), target_register)
             }
+            (Expression::Map(map), DataType::Map(key_type, value_type)) => {
+                self.compile_map(map, Some((key_type, value_type)), target_register)
+            }
+            _ => self.compile_expression(expression, target_register),
         }
     }
 
-    fn compile_let_statement(&mut self, let_statement: &LetStatement) -> Result<(), Error> {
-        if Self::is_keyword(let_statement.identifier().name()) {
+    fn compile_list(
+        &mut self,
+        list: &List,
+        expected_element_type: Option<&DataType>,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        // The elements might read the target register, so the list is built in a new one
+        let list_register = self.register_stack.pop().expect("Ran out of registers");
+        self.bytecode.push(Opcode::NewList(list_register));
+
+        let mut element_type: Option<DataType> = None;
+        for element in list.elements() {
+            match element_type.as_ref().or(expected_element_type) {
+                Some(expected_type) => {
+                    let expected_type = expected_type.clone();
+                    self.compile_expression_as(element, &expected_type, None)?
+                }
+                None => self.compile_expression(element, None)?,
+            }
+            let register = self.get_register()?;
+            match &element_type {
+                // Lists are homogeneous, the first element decides the type of the others
+                Some(element_type) if *element_type != register.data_type => {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::ArgumentInvalidType(
+                            element_type.typename(),
+                            register.data_type.typename(),
+                        ),
+                        list.span(),
+                        element.span(),
+                    ));
+                }
+                Some(_) => (),
+                None => element_type = Some(register.data_type.clone()),
+            }
+            self.bytecode
+                .push(Opcode::ListPush(list_register, register.value));
+            if register.is_temporary {
+                self.register_stack.push(register.value);
+            }
+        }
+
+        let Some(element_type) = element_type.or(expected_element_type.cloned()) else {
             return Err(Error::new(
                 self.filename.clone(),
                 self.source_code.clone(),
-                ErrorKind::IdentifierIsKeyword,
-                let_statement.span(),
-                let_statement.identifier().span(),
+                ErrorKind::UnknownListType,
+                list.span(),
+                list.span(),
+            ));
+        };
+        let result_register = match target_register {
+            Some(reg) => {
+                self.bytecode.push(Opcode::Copy(list_register, reg));
+                self.register_stack.push(list_register);
+                reg
+            }
+            None => list_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            DataType::List(Box::new(element_type)),
+            true,
+        )));
+        Ok(())
+    }
+
+    fn compile_map(
+        &mut self,
+        map: &Map,
+        expected_types: Option<(&DataType, &DataType)>,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        // The entries might read the target register, so the map is built in a new one
+        let map_register = self.register_stack.pop().expect("Ran out of registers");
+        self.bytecode.push(Opcode::NewMap(map_register));
+
+        let mut entry_types: Option<(DataType, DataType)> =
+            expected_types.map(|(key_type, value_type)| (key_type.clone(), value_type.clone()));
+        for (key, value) in map.entries() {
+            match entry_types.clone() {
+                Some((key_type, value_type)) => {
+                    self.compile_expression_as(key, &key_type, None)?;
+                    self.compile_expression_as(value, &value_type, None)?;
+                }
+                None => {
+                    self.compile_expression(key, None)?;
+                    self.compile_expression(value, None)?;
+                }
+            }
+            let value_register = self.get_register()?;
+            let key_register = self.get_register()?;
+            match &entry_types {
+                // Maps are homogeneous, the first entry decides the types of the others
+                Some((key_type, value_type)) => {
+                    for (expected_type, register, expression) in [
+                        (key_type, &key_register, key),
+                        (value_type, &value_register, value),
+                    ] {
+                        if *expected_type != register.data_type {
+                            return Err(Error::new(
+                                self.filename.clone(),
+                                self.source_code.clone(),
+                                ErrorKind::ArgumentInvalidType(
+                                    expected_type.typename(),
+                                    register.data_type.typename(),
+                                ),
+                                map.span(),
+                                expression.span(),
+                            ));
+                        }
+                    }
+                }
+                None => {
+                    self.check_map_key_type(&key_register.data_type, map.span(), key.span())?;
+                    entry_types = Some((
+                        key_register.data_type.clone(),
+                        value_register.data_type.clone(),
+                    ));
+                }
+            }
+            self.bytecode.push(Opcode::MapSet(
+                map_register,
+                key_register.value,
+                value_register.value,
+            ));
+            for register in [value_register, key_register] {
+                if register.is_temporary {
+                    self.register_stack.push(register.value);
+                }
+            }
+        }
+
+        let Some((key_type, value_type)) = entry_types else {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::UnknownMapType,
+                map.span(),
+                map.span(),
+            ));
+        };
+        let result_register = match target_register {
+            Some(reg) => {
+                self.bytecode.push(Opcode::Copy(map_register, reg));
+                self.register_stack.push(map_register);
+                reg
+            }
+            None => map_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            DataType::Map(Box::new(key_type), Box::new(value_type)),
+            true,
+        )));
+        Ok(())
+    }
+
+    fn compile_index(&mut self, index: &Index, target_register: Option<u8>) -> Result<(), Error> {
+        self.compile_expression(index.target(), None)?;
+        let container_register = self.get_register()?;
+        let (member, member_type) = self.compile_subscript(
+            &container_register,
+            index.index(),
+            index.span(),
+            index.target().span(),
+        )?;
+        let index_register = member.index_register().unwrap().clone();
+
+        let target_register = match target_register {
+            Some(reg) => reg,
+            None => {
+                if index_register.is_temporary {
+                    index_register.value
+                } else if container_register.is_temporary {
+                    container_register.value
+                } else {
+                    self.register_stack.pop().expect("Ran out of registers")
+                }
+            }
+        };
+        self.bytecode.push(Self::member_get_opcode(
+            container_register.value,
+            &member,
+            target_register,
+        ));
+        self.operand_stack.push(Operand::Register(Register::new(
+            target_register,
+            member_type,
+            true,
+        )));
+
+        for register in [container_register, index_register] {
+            if register.is_temporary && register.value != target_register {
+                self.register_stack.push(register.value);
+            }
+        }
+        Ok(())
+    }
+
+    // Checks that the register holds a list or map and compiles the index or key into a register.
+    // Returns the accessed member and its type.
+    fn compile_subscript(
+        &mut self,
+        container_register: &Register,
+        index: &Expression,
+        context: Span,
+        container_span: Span,
+    ) -> Result<(Member, DataType), Error> {
+        let (index_type, member_type) = match &container_register.data_type {
+            DataType::List(element_type) => (DataType::Int, *element_type.clone()),
+            DataType::Map(key_type, value_type) => (*key_type.clone(), *value_type.clone()),
+            data_type => {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::NotIndexable(data_type.typename()),
+                    context,
+                    container_span,
+                ));
+            }
+        };
+
+        self.compile_expression(index, None)?;
+        let index_register = self.get_register()?;
+        if index_register.data_type != index_type {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::ArgumentInvalidType(
+                    index_type.typename(),
+                    index_register.data_type.typename(),
+                ),
+                context,
+                index.span(),
+            ));
+        }
+        let member = match container_register.data_type {
+            DataType::List(_) => Member::Element(index_register),
+            _ => Member::Entry(index_register),
+        };
+        Ok((member, member_type))
+    }
+
+    fn compile_struct_literal(
+        &mut self,
+        struct_literal: &StructLiteral,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        let identifier = struct_literal.identifier();
+        let Some(struct_type) = self.structs.get(identifier.name()).cloned() else {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::UnknownType(identifier.name().to_owned()),
+                struct_literal.span(),
+                identifier.span(),
+            ));
+        };
+
+        // The field values might read the target register, so the struct is built in a new one
+        let struct_register = self.register_stack.pop().expect("Ran out of registers");
+        self.bytecode.push(Opcode::NewStruct(
+            struct_register,
+            struct_type.fields.len() as u8,
+        ));
+
+        let mut initialized: Vec<&str> = Vec::new();
+        for (field, value) in struct_literal.fields() {
+            let Some((field_idx, field_type)) = struct_type.field(field.name()) else {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::UnknownField(identifier.name().to_owned(), field.name().to_owned()),
+                    struct_literal.span(),
+                    field.span(),
+                ));
+            };
+            if initialized.contains(&field.name()) {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::DuplicateField(field.name().to_owned()),
+                    struct_literal.span(),
+                    field.span(),
+                ));
+            }
+            initialized.push(field.name());
+
+            self.compile_expression_as(value, field_type, None)?;
+            let register = self.get_register()?;
+            if register.data_type != *field_type {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::ArgumentInvalidType(
+                        field_type.typename(),
+                        register.data_type.typename(),
+                    ),
+                    struct_literal.span(),
+                    value.span(),
+                ));
+            }
+            self.bytecode.push(Opcode::StructSet(
+                struct_register,
+                field_idx,
+                register.value,
+            ));
+            if register.is_temporary {
+                self.register_stack.push(register.value);
+            }
+        }
+
+        // Every field has to be initialized, so reading a field never sees the placeholder value
+        if let Some((missing, _)) = struct_type
+            .fields
+            .iter()
+            .find(|(name, _)| !initialized.contains(&name.as_str()))
+        {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::MissingField(identifier.name().to_owned(), missing.clone()),
+                struct_literal.span(),
+                identifier.span(),
+            ));
+        }
+
+        let result_register = match target_register {
+            Some(reg) => {
+                self.bytecode.push(Opcode::Copy(struct_register, reg));
+                self.register_stack.push(struct_register);
+                reg
+            }
+            None => struct_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            DataType::Struct(identifier.name().to_owned()),
+            true,
+        )));
+        Ok(())
+    }
+
+    fn compile_field_access(
+        &mut self,
+        field_access: &FieldAccess,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        self.compile_expression(field_access.target(), None)?;
+        let struct_register = self.get_register()?;
+        let (field_idx, field_type) = self.resolve_field(
+            &struct_register.data_type,
+            field_access.field(),
+            field_access.span(),
+            field_access.target().span(),
+        )?;
+
+        let target_register = match target_register {
+            Some(reg) => reg,
+            None => {
+                if struct_register.is_temporary {
+                    struct_register.value
+                } else {
+                    self.register_stack.pop().expect("Ran out of registers")
+                }
+            }
+        };
+        self.bytecode.push(Opcode::StructGet(
+            struct_register.value,
+            field_idx,
+            target_register,
+        ));
+        self.operand_stack.push(Operand::Register(Register::new(
+            target_register,
+            field_type,
+            true,
+        )));
+
+        if struct_register.is_temporary && struct_register.value != target_register {
+            self.register_stack.push(struct_register.value);
+        }
+        Ok(())
+    }
+
+    // Returns the position and type of the field in the struct type
+    fn resolve_field(
+        &self,
+        data_type: &DataType,
+        field: &Identifier,
+        context: Span,
+        target_span: Span,
+    ) -> Result<(u8, DataType), Error> {
+        let DataType::Struct(struct_name) = data_type else {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::NoFields(data_type.typename()),
+                context,
+                target_span,
+            ));
+        };
+        match self.structs[struct_name].field(field.name()) {
+            Some((field_idx, field_type)) => Ok((field_idx, field_type.clone())),
+            None => Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::UnknownField(struct_name.clone(), field.name().to_owned()),
+                context,
+                field.span(),
+            )),
+        }
+    }
+
+    // Resolves an index or field of an assignment's target.
+    // Returns the accessed member and its type.
+    fn compile_accessor(
+        &mut self,
+        container: &Register,
+        accessor: &Accessor,
+        assignment: &Assignment,
+    ) -> Result<(Member, DataType), Error> {
+        match accessor {
+            Accessor::Index(index) => {
+                self.compile_subscript(container, index, assignment.span(), assignment.lhs().span())
+            }
+            Accessor::Field(field) => {
+                let (field_idx, field_type) = self.resolve_field(
+                    &container.data_type,
+                    field,
+                    assignment.span(),
+                    assignment.lhs().span(),
+                )?;
+                Ok((Member::Field(field_idx), field_type))
+            }
+        }
+    }
+
+    #[inline]
+    fn member_get_opcode(container: u8, member: &Member, result: u8) -> Opcode {
+        match member {
+            Member::Element(index_register) => {
+                Opcode::ListGet(container, index_register.value, result)
+            }
+            Member::Entry(key_register) => Opcode::MapGet(container, key_register.value, result),
+            Member::Field(field_idx) => Opcode::StructGet(container, *field_idx, result),
+        }
+    }
+
+    #[inline]
+    fn member_set_opcode(container: u8, member: &Member, value: u8) -> Opcode {
+        match member {
+            Member::Element(index_register) => {
+                Opcode::ListSet(container, index_register.value, value)
+            }
+            Member::Entry(key_register) => Opcode::MapSet(container, key_register.value, value),
+            Member::Field(field_idx) => Opcode::StructSet(container, *field_idx, value),
+        }
+    }
+
+    // Assignment to a list element or struct field, e.g. 'xs[i].y += v'
+    fn compile_member_assignment(
+        &mut self,
+        assignment: &Assignment,
+        lhs_reg: Register,
+    ) -> Result<(), Error> {
+        let mut temporaries: Vec<u8> = Vec::new();
+        // Every accessor but the last one selects the value which is written to
+        let (last_accessor, accessors) = assignment.accessors().split_last().unwrap();
+        let mut container_register = lhs_reg;
+        for accessor in accessors {
+            let (member, member_type) =
+                self.compile_accessor(&container_register, accessor, assignment)?;
+            let member_register = self.register_stack.pop().expect("Ran out of registers");
+            self.bytecode.push(Self::member_get_opcode(
+                container_register.value,
+                &member,
+                member_register,
+            ));
+            if let Some(index_register) = member.index_register()
+                && index_register.is_temporary
+            {
+                self.register_stack.push(index_register.value);
+            }
+            temporaries.push(member_register);
+            container_register = Register::new(member_register, member_type, true);
+        }
+        let (member, member_type) =
+            self.compile_accessor(&container_register, last_accessor, assignment)?;
+        if let Some(index_register) = member.index_register()
+            && index_register.is_temporary
+        {
+            temporaries.push(index_register.value);
+        }
+
+        let value_register = match assignment.operator().binary_operator() {
+            None => {
+                self.compile_expression_as(assignment.rhs(), &member_type, None)?;
+                self.get_register()?
+            }
+            Some(operator) => {
+                let member_register = self.register_stack.pop().expect("Ran out of registers");
+                self.bytecode.push(Self::member_get_opcode(
+                    container_register.value,
+                    &member,
+                    member_register,
+                ));
+                let member_register = Register::new(member_register, member_type.clone(), true);
+
+                self.compile_expression(assignment.rhs(), None)?;
+                let rhs_reg = self.get_register()?;
+                let (promoted_member_reg, promoted_rhs_reg) =
+                    match self.promote_operands(operator, member_register.clone(), rhs_reg.clone())
+                    {
+                        Some(promoted) => promoted,
+                        None => (member_register.clone(), rhs_reg.clone()),
+                    };
+                let Some(data_type) = self.compile_binary_operator(
+                    operator,
+                    &promoted_member_reg,
+                    &promoted_rhs_reg,
+                    member_register.value,
+                ) else {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::InvalidBinaryOperation(
+                            operator,
+                            member_type.typename(),
+                            rhs_reg.data_type.typename(),
+                        ),
+                        assignment.span(),
+                        assignment.operator_span(),
+                    ));
+                };
+                if promoted_rhs_reg.is_temporary {
+                    self.register_stack.push(promoted_rhs_reg.value);
+                }
+                Register::new(member_register.value, data_type, true)
+            }
+        };
+        if value_register.data_type != member_type {
+            return Err(self.new_invalid_assignment_error(
+                member_type.typename(),
+                value_register.data_type.typename(),
+                assignment.span(),
+                assignment.operator_span(),
+            ));
+        }
+        self.bytecode.push(Self::member_set_opcode(
+            container_register.value,
+            &member,
+            value_register.value,
+        ));
+
+        if value_register.is_temporary {
+            self.register_stack.push(value_register.value);
+        }
+        for register in temporaries {
+            self.register_stack.push(register);
+        }
+        Ok(())
+    }
+
+    // Compiles a call to one of the builtin list, map and I/O functions.
+    // Returns None if there is no builtin with the given name, otherwise whether the call produced a value.
+    fn compile_builtin_call(
+        &mut self,
+        name: &str,
+        call: &Call,
+        target_register: Option<u8>,
+        requires_value: bool,
+    ) -> Result<Option<bool>, Error> {
+        let parameter_count = match name {
+            "read_line" => 0,
+            "len" | "pop" | "keys" | "print" | "println" => 1,
+            "push" | "contains" | "remove" => 2,
+            _ => return Ok(None),
+        };
+        if call.arguments().len() != parameter_count {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::ArgumentCountMismatch(parameter_count, call.arguments().len()),
+                call.span(),
+                call.span(),
+            ));
+        }
+        if matches!(name, "push" | "print" | "println") && requires_value {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::NoReturnValue(name.to_owned()),
+                call.span(),
+                call.span(),
+            ));
+        }
+
+        match name {
+            "read_line" => {
+                let result_register = match target_register {
+                    Some(reg) => reg,
+                    None => self.register_stack.pop().expect("Ran out of registers"),
+                };
+                self.bytecode.push(Opcode::ReadLine(result_register));
+                self.operand_stack.push(Operand::Register(Register::new(
+                    result_register,
+                    DataType::Str,
+                    true,
+                )));
+                return Ok(Some(true));
+            }
+            // Every type can be printed, so there is nothing to check
+            "print" | "println" => {
+                self.compile_expression(&call.arguments()[0], None)?;
+                let register = self.get_register()?;
+                self.bytecode.push(match name {
+                    "print" => Opcode::Print(register.value),
+                    _ => Opcode::PrintLn(register.value),
+                });
+                if register.is_temporary {
+                    self.register_stack.push(register.value);
+                }
+                return Ok(Some(false));
+            }
+            _ => {}
+        }
+
+        let collection_argument = &call.arguments()[0];
+        self.compile_expression(collection_argument, None)?;
+        let collection_register = self.get_register()?;
+        // The type of the second argument, which is the value to push or the key to look up
+        let argument_type = match (name, &collection_register.data_type) {
+            ("len", DataType::List(_) | DataType::Map(..)) => None,
+            ("pop", DataType::List(_)) | ("keys", DataType::Map(..)) => None,
+            ("push", DataType::List(element_type)) => Some(*element_type.clone()),
+            ("contains" | "remove", DataType::Map(key_type, _)) => Some(*key_type.clone()),
+            (_, data_type) => {
+                let kind = match name {
+                    "len" => ErrorKind::ExpectedCollection(data_type.typename()),
+                    "pop" | "push" => ErrorKind::ExpectedList(data_type.typename()),
+                    _ => ErrorKind::ExpectedMap(data_type.typename()),
+                };
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    kind,
+                    call.span(),
+                    collection_argument.span(),
+                ));
+            }
+        };
+
+        let argument_register = match argument_type {
+            Some(argument_type) => {
+                let argument = &call.arguments()[1];
+                self.compile_expression_as(argument, &argument_type, None)?;
+                let argument_register = self.get_register()?;
+                if argument_register.data_type != argument_type {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::ArgumentInvalidType(
+                            argument_type.typename(),
+                            argument_register.data_type.typename(),
+                        ),
+                        call.span(),
+                        argument.span(),
+                    ));
+                }
+                Some(argument_register)
+            }
+            None => None,
+        };
+
+        let collection = collection_register.value;
+        let result = if name == "push" {
+            let value = argument_register.as_ref().unwrap().value;
+            self.bytecode.push(Opcode::ListPush(collection, value));
+            false
+        } else {
+            let result_register = match target_register {
+                Some(reg) => reg,
+                None => self.register_stack.pop().expect("Ran out of registers"),
+            };
+            let key = argument_register.as_ref().map(|register| register.value);
+            let (opcode, data_type) = match (name, &collection_register.data_type) {
+                ("len", DataType::List(_)) => {
+                    (Opcode::ListLen(collection, result_register), DataType::Int)
+                }
+                ("len", _) => (Opcode::MapLen(collection, result_register), DataType::Int),
+                ("pop", DataType::List(element_type)) => (
+                    Opcode::ListPop(collection, result_register),
+                    *element_type.clone(),
+                ),
+                ("keys", DataType::Map(key_type, _)) => (
+                    Opcode::MapKeys(collection, result_register),
+                    DataType::List(key_type.clone()),
+                ),
+                ("contains", _) => (
+                    Opcode::MapContains(collection, key.unwrap(), result_register),
+                    DataType::Bool,
+                ),
+                ("remove", DataType::Map(_, value_type)) => (
+                    Opcode::MapRemove(collection, key.unwrap(), result_register),
+                    *value_type.clone(),
+                ),
+                _ => unreachable!(),
+            };
+            self.bytecode.push(opcode);
+            self.operand_stack.push(Operand::Register(Register::new(
+                result_register,
+                data_type,
+                true,
+            )));
+            true
+        };
+
+        if let Some(argument_register) = argument_register
+            && argument_register.is_temporary
+        {
+            self.register_stack.push(argument_register.value);
+        }
+        if collection_register.is_temporary {
+            self.register_stack.push(collection_register.value);
+        }
+        Ok(Some(result))
+    }
+
+    // Converts an int operand mixed with a float operand to float if int promotion is enabled.
+    // Returns None if no conversion is needed.
+    fn promote_operands(
+        &mut self,
+        operator: BinaryOperator,
+        left: Register,
+        right: Register,
+    ) -> Option<(Register, Register)> {
+        // Logical and bitwise operators are not defined for floats either,
+        // so promoting would only hide the actual types
+        if !self.int_promotion
+            || matches!(
+                operator,
+                BinaryOperator::Or
+                    | BinaryOperator::And
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight
+            )
+        {
+            return None;
+        }
+        match (&left.data_type, &right.data_type) {
+            (DataType::Int, DataType::Float) => Some((self.compile_int_to_float(left), right)),
+            (DataType::Float, DataType::Int) => Some((left, self.compile_int_to_float(right))),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn compile_int_to_float(&mut self, register: Register) -> Register {
+        let target_register = if register.is_temporary {
+            register.value
+        } else {
+            self.register_stack.pop().expect("Ran out of registers")
+        };
+        self.bytecode
+            .push(Opcode::IntToFloat(register.value, target_register));
+        Register::new(target_register, DataType::Float, true)
+    }
+
+    // Looks up a variable, capturing it from the enclosing scopes if the current function is a closure
+    fn resolve_variable(&mut self, name: &str) -> Option<Register> {
+        self.resolve_variable_at(name, self.function_contexts.len())
+    }
+
+    fn resolve_variable_at(&mut self, name: &str, level: usize) -> Option<Register> {
+        let scopes = if level == self.function_contexts.len() {
+            &self.scopes
+        } else {
+            &self.function_contexts[level].enclosing_scopes
+        };
+        if let Some(register) = scopes.iter().rev().find_map(|scope| scope.get(name)) {
+            return Some(register.clone());
+        }
+        if level == 0 || !self.function_contexts[level - 1].is_closure {
+            return None;
+        }
+
+        let outer_register = self.resolve_variable_at(name, level - 1)?;
+        let is_unassigned = self.function_contexts[level - 1]
+            .enclosing_unassigned
+            .contains(&outer_register.value);
+        // The bottom of the free list has never been handed out, so no earlier temporary
+        // in the body can clobber the captured value which is placed there on every call
+        let (register_stack, scopes, unassigned) = if level == self.function_contexts.len() {
+            (
+                &mut self.register_stack,
+                &mut self.scopes,
+                &mut self.unassigned,
+            )
+        } else {
+            let context = &mut self.function_contexts[level];
+            (
+                &mut context.enclosing_register_stack,
+                &mut context.enclosing_scopes,
+                &mut context.enclosing_unassigned,
+            )
+        };
+        assert!(!register_stack.is_empty(), "Ran out of registers");
+        let register = Register::new(register_stack.remove(0), outer_register.data_type, false);
+        // Captures live in the outermost scope, so they stay valid for the whole body
+        scopes[0].insert(name.to_owned(), register.clone());
+        // Capturing a variable without a value gives a copy without a value
+        if is_unassigned {
+            unassigned.insert(register.value);
+        }
+        let body_start = self.function_contexts[level - 1].body_start;
+        self.track_variable(name, register.value, level, body_start);
+        self.function_contexts[level - 1]
+            .captures
+            .push((outer_register.value, register.value));
+        Some(register)
+    }
+
+    fn always_returns(control_flow_structures: &[ControlFlow]) -> bool {
+        control_flow_structures
+            .iter()
+            .any(|control_flow| match control_flow {
+                ControlFlow::BasicBlock(basic_block) => basic_block
+                    .statements()
+                    .iter()
+                    .any(|statement| matches!(statement, Statement::ReturnStatement(_))),
+                ControlFlow::If(if_statement) => match if_statement.else_body() {
+                    Some(else_body) => {
+                        Self::always_returns(else_body.control_flow_structures())
+                            && if_statement.branches().iter().all(|branch| {
+                                Self::always_returns(branch.body().control_flow_structures())
+                            })
+                    }
+                    None => false,
+                },
+                ControlFlow::WhileLoop(_)
+                | ControlFlow::FunctionDeclaration(_)
+                | ControlFlow::StructDeclaration(_) => false,
+            })
+    }
+
+    fn compile_while_loop(&mut self, while_loop: &WhileLoop) -> Result<(), Error> {
+        let start_idx = self.bytecode.len();
+        self.compile_expression(while_loop.condition(), None)?;
+        let result_register = self.get_register()?;
+        if result_register.data_type != DataType::Bool {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::ArgumentInvalidType(
+                    DataType::Bool.typename(),
+                    result_register.data_type.typename(),
+                ),
+                while_loop.span(),
+                while_loop.condition().span(),
+            ));
+        }
+
+        let conditional_jump_opcode_idx = self.bytecode.len();
+        // Placeholder to be replaced later when we know the actual index where the loop ends
+        self.bytecode.push(Opcode::Error);
+        self.record_spans(while_loop.condition().span());
+
+        // The body might not run at all, so its assignments do not count after the loop
+        let unassigned = self.unassigned.clone();
+        self.loop_contexts.push(LoopContext::new(start_idx));
+        let body_result = self.compile_block(while_loop.body());
+        let loop_context = self.loop_contexts.pop().unwrap();
+        body_result?;
+        self.unassigned = unassigned;
+
+        // We are inside the loop, we need to jump backwards to before the conditional expression.
+        let offset = Self::backward_jump_offset(self.bytecode.len(), start_idx);
+        self.bytecode.push(Opcode::Jump(offset));
+        self.record_spans(while_loop.span());
+
+        // jump to after the loop is over in case the conditional expression evaluates to 'false'
+        let end_idx = self.bytecode.len();
+        let offset = Self::forward_jump_offset(conditional_jump_opcode_idx, end_idx);
+        self.bytecode[conditional_jump_opcode_idx] =
+            Opcode::JumpCond(result_register.value, offset);
+
+        for jump_opcode_idx in loop_context.break_jump_indices {
+            let offset = Self::forward_jump_offset(jump_opcode_idx, end_idx);
+            self.bytecode[jump_opcode_idx] = Opcode::Jump(offset);
+        }
+
+        Ok(())
+    }
+
+    fn compile_if_statement(&mut self, if_statement: &IfStatement) -> Result<(), Error> {
+        let branch_count = if_statement.branches().len();
+        let has_else = if_statement.else_body().is_some();
+        let mut end_jump_opcode_indices: Vec<usize> = Vec::new();
+        // A variable is only assigned after the statement if every branch which falls through assigns it
+        let unassigned = self.unassigned.clone();
+        let mut unassigned_after: HashSet<u8> = HashSet::new();
+
+        for (i, branch) in if_statement.branches().iter().enumerate() {
+            self.compile_expression(branch.condition(), None)?;
+            let result_register = self.get_register()?;
+            if result_register.data_type != DataType::Bool {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::ArgumentInvalidType(
+                        DataType::Bool.typename(),
+                        result_register.data_type.typename(),
+                    ),
+                    branch.span(),
+                    branch.condition().span(),
+                ));
+            }
+            if result_register.is_temporary {
+                self.register_stack.push(result_register.value);
+            }
+
+            let conditional_jump_opcode_idx = self.bytecode.len();
+            // Placeholder to be replaced once we know where the branch body ends
+            self.bytecode.push(Opcode::Error);
+            self.record_spans(branch.condition().span());
+
+            self.unassigned = unassigned.clone();
+            self.compile_block(branch.body())?;
+            if !Self::always_returns(branch.body().control_flow_structures()) {
+                unassigned_after.extend(self.unassigned.drain());
+            }
+
+            // Every branch except the very last one has to skip the remaining branches when it is taken
+            if i + 1 < branch_count || has_else {
+                end_jump_opcode_indices.push(self.bytecode.len());
+                self.bytecode.push(Opcode::Error);
+                self.record_spans(branch.span());
+            }
+
+            // Jump to the next branch in case the condition evaluates to 'false'
+            let offset =
+                Self::forward_jump_offset(conditional_jump_opcode_idx, self.bytecode.len());
+            self.bytecode[conditional_jump_opcode_idx] =
+                Opcode::JumpCond(result_register.value, offset);
+        }
+
+        // Without an else branch none of the branches might run, which keeps the state from before
+        self.unassigned = unassigned;
+        if let Some(else_body) = if_statement.else_body() {
+            self.compile_block(else_body)?;
+            if Self::always_returns(else_body.control_flow_structures()) {
+                self.unassigned.clear();
+            }
+        }
+        self.unassigned.extend(unassigned_after);
+
+        let end_idx = self.bytecode.len();
+        for jump_opcode_idx in end_jump_opcode_indices {
+            let offset = Self::forward_jump_offset(jump_opcode_idx, end_idx);
+            self.bytecode[jump_opcode_idx] = Opcode::Jump(offset);
+        }
+
+        Ok(())
+    }
+
+    // The -1 is needed because the program counter is expected to increment after each operation as well
+    #[inline]
+    fn forward_jump_offset(jump_opcode_idx: usize, target_idx: usize) -> i16 {
+        let offset = target_idx - jump_opcode_idx - 1;
+        if offset > i16::MAX as usize {
+            panic!("Jump offset cannot fit into i16");
+        }
+        offset as i16
+    }
+
+    // The +1 is needed because the program counter is expected to increment after each operation as well,
+    // including this jump, so in thought we have to be one operation ahead already.
+    #[inline]
+    fn backward_jump_offset(jump_opcode_idx: usize, target_idx: usize) -> i16 {
+        let offset = jump_opcode_idx - target_idx + 1;
+        if offset > i16::MAX as usize {
+            panic!("Jump offset cannot fit into i16");
+        }
+        -(offset as i16)
+    }
+
+    fn compile_block(&mut self, block: &Block) -> Result<(), Error> {
+        self.scopes.push(Scope::new());
+        for control_flow in block.control_flow_structures() {
+            self.compile_control_flow(control_flow)?;
+        }
+        self.end_scope();
+        Ok(())
+    }
+
+    // Releases the registers of all variables declared in the innermost scope
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        let depth = self.function_contexts.len();
+        for (name, register) in scope {
+            self.untrack_variable(&name, depth);
+            self.register_stack.push(register.value);
+            self.unassigned.remove(&register.value);
+            self.closed_variables.insert(name);
+        }
+    }
+
+    #[inline]
+    fn check_assigned(
+        &self,
+        register: &Register,
+        identifier: &Identifier,
+        context: Span,
+    ) -> Result<(), Error> {
+        if self.unassigned.contains(&register.value) {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::IdentifierNotAssigned,
+                context,
+                identifier.span(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn declare_variable(&mut self, name: &str, register: Register) {
+        let depth = self.function_contexts.len();
+        let scope = self.scopes.last_mut().unwrap();
+        let register_value = register.value;
+        // Shadowing a variable of the same scope makes the old one unreachable
+        if let Some(shadowed) = scope.insert(name.to_owned(), register) {
+            self.register_stack.push(shadowed.value);
+            self.unassigned.remove(&shadowed.value);
+            self.untrack_variable(name, depth);
+        }
+        self.track_variable(name, register_value, depth, self.bytecode.len());
+    }
+
+    fn compile_basic_block(&mut self, basic_block: &BasicBlock) -> Result<(), Error> {
+        for statement in basic_block.statements() {
+            self.compile_statement(statement)?;
+            self.record_spans(statement.span());
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, statement: &Statement) -> Result<(), Error> {
+        match statement {
+            Statement::LetStatement(let_statement) => self.compile_let_statement(let_statement),
+            Statement::Assignment(assignemnt) => self.compile_assignment(assignemnt),
+            Statement::ReturnStatement(return_statement) => {
+                self.compile_return_statement(return_statement)
+            }
+            Statement::Break(span) => {
+                let jump_opcode_idx = self.bytecode.len();
+                let Some(loop_context) = self.loop_contexts.last_mut() else {
+                    return Err(self.new_outside_loop_error("break", *span));
+                };
+                loop_context.break_jump_indices.push(jump_opcode_idx);
+                // Placeholder to be replaced once the end of the enclosing loop is known
+                self.bytecode.push(Opcode::Error);
+                Ok(())
+            }
+            Statement::Continue(span) => {
+                let Some(loop_context) = self.loop_contexts.last() else {
+                    return Err(self.new_outside_loop_error("continue", *span));
+                };
+                let offset =
+                    Self::backward_jump_offset(self.bytecode.len(), loop_context.start_idx);
+                self.bytecode.push(Opcode::Jump(offset));
+                Ok(())
+            }
+            Statement::Expression(Expression::Call(call)) => {
+                // Calls are the only expressions which might not produce a value at all
+                if self.compile_call(call, None, false)? {
+                    let result = self.get_register()?;
+                    self.register_stack.push(result.value);
+                }
+                Ok(())
+            }
+            Statement::Expression(expression) => {
+                self.compile_expression(expression, None)?;
+
+                // Discard the value of standalone expressions and release the register holding it
+                let result = self.get_register()?;
+                if result.is_temporary {
+                    self.register_stack.push(result.value);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn compile_let_statement(&mut self, let_statement: &LetStatement) -> Result<(), Error> {
+        if Self::is_keyword(let_statement.identifier().name()) {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::IdentifierIsKeyword,
+                let_statement.span(),
+                let_statement.identifier().span(),
+            ));
+        }
+        if let Some(type_annotation) = let_statement.type_annotation() {
+            self.check_type(
+                type_annotation.data_type(),
+                let_statement.span(),
+                type_annotation.span(),
+            )?;
+        }
+        let Some(expression) = let_statement.expression() else {
+            // The grammar only allows a declaration without a value if it has a type annotation
+            let data_type = let_statement.type_annotation().unwrap().data_type().clone();
+            let register = self.register_stack.pop().expect("Ran out of registers");
+            self.unassigned.insert(register);
+            self.declare_variable(
+                let_statement.identifier().name(),
+                Register::new(register, data_type, false),
+            );
+            return Ok(());
+        };
+
+        match let_statement.type_annotation() {
+            Some(type_annotation) => {
+                self.compile_expression_as(expression, type_annotation.data_type(), None)?
+            }
+            None => self.compile_expression(expression, None)?,
+        }
+        let mut result_register = self.get_register()?;
+        if let Some(type_annotation) = let_statement.type_annotation()
+            && *type_annotation.data_type() != result_register.data_type
+        {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::ArgumentInvalidType(
+                    type_annotation.data_type().typename(),
+                    result_register.data_type.typename(),
+                ),
+                let_statement.span(),
+                type_annotation.span(),
+            ));
+        }
+        if !result_register.is_temporary {
+            // The register belongs to another variable, so the new variable needs its own copy
+            let register = self.register_stack.pop().expect("Ran out of registers");
+            self.bytecode
+                .push(Opcode::Copy(result_register.value, register));
+            result_register.value = register;
+        }
+        result_register.is_temporary = false;
+        self.declare_variable(let_statement.identifier().name(), result_register);
+        Ok(())
+    }
+
+    fn compile_assignment(&mut self, assignment: &Assignment) -> Result<(), Error> {
+        let lhs_reg = match self.resolve_variable(assignment.lhs().name()) {
+            Some(reg) => reg,
+            None => {
+                return Err(
+                    self.new_identifier_not_found_error(assignment.lhs(), assignment.span())
+                );
+            }
+        };
+
+        if !assignment.accessors().is_empty() {
+            self.check_assigned(&lhs_reg, assignment.lhs(), assignment.span())?;
+            return self.compile_member_assignment(assignment, lhs_reg);
+        }
+
+        if let Some(operator) = assignment.operator().binary_operator() {
+            self.check_assigned(&lhs_reg, assignment.lhs(), assignment.span())?;
+            return self.compile_compound_assignment(assignment, lhs_reg, operator);
+        }
+
+        match assignment.rhs() {
+            Expression::Literal(literal) => {
+                if lhs_reg.data_type == literal.value().data_type() {
+                    self.compile_load_value(
+                        lhs_reg.value,
+                        literal.value().clone(),
+                        assignment.rhs().span(),
+                    )?;
+                } else {
+                    return Err(self.new_invalid_assignment_error(
+                        lhs_reg.data_type.typename(),
+                        literal.value().data_type().typename(),
+                        assignment.span(),
+                        assignment.operator_span(),
+                    ));
+                }
+            }
+            Expression::Identifier(identifier) => match self.resolve_variable(identifier.name()) {
+                Some(rhs_reg) => {
+                    self.check_assigned(&rhs_reg, identifier, assignment.span())?;
+                    if lhs_reg.data_type == rhs_reg.data_type {
+                        self.bytecode
+                            .push(Opcode::Copy(rhs_reg.value, lhs_reg.value));
+                    } else {
+                        return Err(self.new_invalid_assignment_error(
+                            lhs_reg.data_type.typename(),
+                            rhs_reg.data_type.typename(),
+                            assignment.span(),
+                            assignment.operator_span(),
+                        ));
+                    }
+                }
+                // Named functions are turned into a new value
+                None => self.compile_assignment_expression(assignment, lhs_reg.clone())?,
+            },
+            Expression::Interpolation(_)
+            | Expression::BinaryOperation(_)
+            | Expression::UnaryOperation(_)
+            | Expression::Call(_)
+            | Expression::Lambda(_)
+            | Expression::Cast(_)
+            | Expression::List(_)
+            | Expression::Map(_)
+            | Expression::Index(_)
+            | Expression::StructLiteral(_)
+            | Expression::FieldAccess(_) => {
+                self.compile_assignment_expression(assignment, lhs_reg.clone())?
+            }
+        }
+        self.unassigned.remove(&lhs_reg.value);
+        Ok(())
+    }
+
+    // 'x op= y' is lowered to the typed opcode of 'x op y' which writes its result back into 'x'
+    fn compile_compound_assignment(
+        &mut self,
+        assignment: &Assignment,
+        lhs_reg: Register,
+        operator: BinaryOperator,
+    ) -> Result<(), Error> {
+        self.compile_expression(assignment.rhs(), None)?;
+        let rhs_reg = self.get_register()?;
+        let (promoted_lhs_reg, promoted_rhs_reg) =
+            match self.promote_operands(operator, lhs_reg.clone(), rhs_reg.clone()) {
+                Some(promoted) => promoted,
+                None => (lhs_reg.clone(), rhs_reg.clone()),
+            };
+
+        let Some(data_type) = self.compile_binary_operator(
+            operator,
+            &promoted_lhs_reg,
+            &promoted_rhs_reg,
+            lhs_reg.value,
+        ) else {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::InvalidBinaryOperation(
+                    operator,
+                    lhs_reg.data_type.typename(),
+                    rhs_reg.data_type.typename(),
+                ),
+                assignment.span(),
+                assignment.operator_span(),
+            ));
+        };
+        if data_type != lhs_reg.data_type {
+            return Err(self.new_invalid_assignment_error(
+                lhs_reg.data_type.typename(),
+                data_type.typename(),
+                assignment.span(),
+                assignment.operator_span(),
+            ));
+        }
+
+        if promoted_rhs_reg.is_temporary {
+            self.register_stack.push(promoted_rhs_reg.value);
+        }
+        Ok(())
+    }
+
+    fn compile_assignment_expression(
+        &mut self,
+        assignment: &Assignment,
+        lhs_reg: Register,
+    ) -> Result<(), Error> {
+        self.compile_expression_as(assignment.rhs(), &lhs_reg.data_type, Some(lhs_reg.value))?;
+        let expression_result = self.get_register()?;
+
+        if lhs_reg.data_type != expression_result.data_type {
+            return Err(self.new_invalid_assignment_error(
+                lhs_reg.data_type.typename(),
+                expression_result.data_type.typename(),
+                assignment.span(),
+                assignment.operator_span(),
             ));
         }
-        self.compile_expression(let_statement.expression(), None)?;
-        let mut result_register = self.get_register();
-        result_register.is_temporary = false;
-        self.variables.insert(
-            let_statement.identifier().name().to_owned(),
-            result_register,
-        );
         Ok(())
     }
 
-    fn compile_assignment(&mut self, assignment: &Assignment) -> Result<(), Error> {
-        let lhs_reg = match self.variables.get(assignment.lhs().name()) {
-            Some(reg) => reg,
+    fn compile_return_statement(
+        &mut self,
+        return_statement: &ReturnStatement,
+    ) -> Result<(), Error> {
+        let (name, return_type) = match self.function_contexts.last() {
+            Some(context) => (context.name.clone(), context.return_type.clone()),
             None => {
-                return Err(
-                    self.new_identifier_not_found_error(assignment.lhs(), assignment.span())
-                );
+                // At the top level 'return' only stores the value for the host to pick up
+                let Some(expression) = return_statement.expression() else {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::MissingReturnValue,
+                        return_statement.span(),
+                        return_statement.span(),
+                    ));
+                };
+                self.compile_expression(expression, None)?;
+                let result_register = self.get_register()?;
+                self.bytecode.push(Opcode::Save(result_register.value));
+                return Ok(());
             }
         };
 
-        match assignment.rhs() {
-            Expression::Literal(literal) => {
-                if lhs_reg.data_type == literal.value().data_type() {
-                    match literal.value().clone() {
-                        Value::Int(val) => {
-                            self.bytecode.push(Opcode::LoadInt(lhs_reg.value, val));
-                        }
-                        Value::Float(val) => {
-                            self.bytecode.push(Opcode::LoadFloat(lhs_reg.value, val));
-                        }
-                        Value::Bool(val) => {
-                            self.bytecode.push(Opcode::LoadBool(lhs_reg.value, val));
-                        }
-                        Value::Str(val) => {
-                            self.bytecode.push(Opcode::LoadStr(lhs_reg.value, val));
-                        }
-                        Value::Char(val) => {
-                            self.bytecode.push(Opcode::LoadChar(lhs_reg.value, val));
-                        }
-                    }
-                } else {
-                    return Err(self.new_invalid_assignment_error(
-                        lhs_reg.data_type.typename(),
-                        literal.value().data_type().typename(),
-                        assignment.span(),
-                        assignment.operator_span(),
+        match (return_type, return_statement.expression()) {
+            (Some(return_type), Some(expression)) => {
+                self.compile_expression_as(expression, &return_type, None)?;
+                let result_register = self.get_register()?;
+                if result_register.data_type != return_type {
+                    return Err(Error::new(
+                        self.filename.clone(),
+                        self.source_code.clone(),
+                        ErrorKind::ArgumentInvalidType(
+                            return_type.typename(),
+                            result_register.data_type.typename(),
+                        ),
+                        return_statement.span(),
+                        expression.span(),
                     ));
                 }
+                self.bytecode.push(Opcode::Return(result_register.value));
+                if result_register.is_temporary {
+                    self.register_stack.push(result_register.value);
+                }
             }
-            Expression::Identifier(identifier) => {
-                let rhs_reg = match self.variables.get(identifier.name()) {
-                    Some(reg) => reg,
+            (Some(_), None) => {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::MissingReturnValue,
+                    return_statement.span(),
+                    return_statement.span(),
+                ));
+            }
+            (None, Some(expression)) => {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::NoReturnValue(name),
+                    return_statement.span(),
+                    expression.span(),
+                ));
+            }
+            (None, None) => self.bytecode.push(Opcode::ReturnVoid),
+        }
+        Ok(())
+    }
+
+    // Returns whether the call produced a value on the operand stack
+    fn compile_call(
+        &mut self,
+        call: &Call,
+        target_register: Option<u8>,
+        requires_value: bool,
+    ) -> Result<bool, Error> {
+        // Named functions are called directly, everything else has to evaluate to a function value
+        let named_function = match call.callee() {
+            Expression::Identifier(identifier)
+                if self.resolve_variable(identifier.name()).is_none() =>
+            {
+                match self.functions.get(identifier.name()) {
+                    Some(function) => Some((identifier.name().to_owned(), function.clone())),
                     None => {
-                        return Err(
-                            self.new_identifier_not_found_error(identifier, assignment.span())
-                        );
+                        if let Some(produced_value) = self.compile_builtin_call(
+                            identifier.name(),
+                            call,
+                            target_register,
+                            requires_value,
+                        )? {
+                            return Ok(produced_value);
+                        }
+                        return Err(self.new_identifier_not_found_error(identifier, call.span()));
                     }
+                }
+            }
+            _ => None,
+        };
+        let (name, function, callee_register) = match named_function {
+            Some((name, function)) => (name, function, None),
+            None => {
+                self.compile_expression(call.callee(), None)?;
+                let register = self.get_register()?;
+                let DataType::Function(parameters, return_type) = &register.data_type else {
+                    return Err(self.new_not_callable_error(call, &register.data_type));
+                };
+                let name = match call.callee() {
+                    Expression::Identifier(identifier) => identifier.name().to_owned(),
+                    _ => register.data_type.typename(),
                 };
+                let function = Function {
+                    address: 0,
+                    parameters: parameters.clone(),
+                    return_type: return_type.as_deref().cloned(),
+                };
+                (name, function, Some(register))
+            }
+        };
 
-                if lhs_reg.data_type == rhs_reg.data_type {
-                    self.bytecode
-                        .push(Opcode::Copy(rhs_reg.value, lhs_reg.value));
-                } else {
-                    return Err(self.new_invalid_assignment_error(
-                        lhs_reg.data_type.typename(),
-                        rhs_reg.data_type.typename(),
-                        assignment.span(),
-                        assignment.operator_span(),
-                    ));
-                }
+        if function.parameters.len() != call.arguments().len() {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::ArgumentCountMismatch(function.parameters.len(), call.arguments().len()),
+                call.span(),
+                call.span(),
+            ));
+        }
+
+        let mut argument_registers: Vec<Register> = Vec::with_capacity(call.arguments().len());
+        for (argument, parameter_type) in call.arguments().iter().zip(&function.parameters) {
+            self.compile_expression_as(argument, parameter_type, None)?;
+            let register = self.get_register()?;
+            if register.data_type != *parameter_type {
+                return Err(Error::new(
+                    self.filename.clone(),
+                    self.source_code.clone(),
+                    ErrorKind::ArgumentInvalidType(
+                        parameter_type.typename(),
+                        register.data_type.typename(),
+                    ),
+                    call.span(),
+                    argument.span(),
+                ));
+            }
+            argument_registers.push(register);
+        }
+        for register in &argument_registers {
+            self.bytecode.push(Opcode::PushArg(register.value));
+        }
+        for register in argument_registers {
+            if register.is_temporary {
+                self.register_stack.push(register.value);
             }
-            Expression::BinaryOperation(_) | Expression::UnaryOperation(_) => {
-                let lhs_data_type = lhs_reg.data_type;
-                self.compile_expression(assignment.rhs(), Some(lhs_reg.value))?;
-                let expression_result = self.get_register();
+        }
 
-                if lhs_data_type != expression_result.data_type {
-                    return Err(self.new_invalid_assignment_error(
-                        lhs_data_type.typename(),
-                        expression_result.data_type.typename(),
-                        assignment.span(),
-                        assignment.operator_span(),
-                    ));
+        if function.return_type.is_none() && requires_value {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::NoReturnValue(name),
+                call.span(),
+                call.span(),
+            ));
+        }
+
+        let arg_count = call.arguments().len() as u8;
+        // The result register is never written by functions without a return value
+        let result_register = match (&function.return_type, target_register) {
+            (Some(_), Some(reg)) => reg,
+            (Some(_), None) => self.register_stack.pop().expect("Ran out of registers"),
+            (None, _) => 0,
+        };
+        match &callee_register {
+            Some(callee_register) => {
+                self.bytecode.push(Opcode::CallValue(
+                    callee_register.value,
+                    arg_count,
+                    result_register,
+                ));
+                if callee_register.is_temporary && callee_register.value != result_register {
+                    self.register_stack.push(callee_register.value);
                 }
             }
+            None => self
+                .bytecode
+                .push(Opcode::Call(function.address, arg_count, result_register)),
+        }
+
+        match function.return_type {
+            Some(return_type) => {
+                self.operand_stack.push(Operand::Register(Register::new(
+                    result_register,
+                    return_type,
+                    true,
+                )));
+                Ok(true)
+            }
+            None => Ok(false),
         }
-        Ok(())
     }
 
-    fn compile_return_statement(
-        &mut self,
-        return_statement: &ReturnStatement,
-    ) -> Result<(), Error> {
-        self.compile_expression(return_statement.expression(), None)?;
-        let result_register = self.get_register();
-        self.bytecode.push(Opcode::Save(result_register.value));
+    // Small ints are encoded in the instruction, other values are loaded from the constant pool
+    fn compile_load_value(&mut self, register: u8, value: Value, span: Span) -> Result<(), Error> {
+        let constant = match value {
+            Value::Int(val) => match i16::try_from(val) {
+                Ok(val) => {
+                    self.bytecode.push(Opcode::LoadNum(register, val));
+                    return Ok(());
+                }
+                Err(_) => Constant::Int(val),
+            },
+            Value::Bool(val) => {
+                self.bytecode.push(Opcode::LoadBool(register, val));
+                return Ok(());
+            }
+            Value::Float(val) => Constant::Float(val),
+            Value::Str(val) => Constant::Str(Rc::new(*val)),
+            Value::Char(val) => Constant::Char(val),
+        };
+        let Ok(idx) = self.constants.add(constant) else {
+            return Err(Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::TooManyConstants,
+                span,
+                span,
+            ));
+        };
+        self.bytecode.push(Opcode::LoadConst(register, idx));
         Ok(())
     }
 
     #[inline]
-    fn get_register(&mut self) -> Register {
+    fn get_register(&mut self) -> Result<Register, Error> {
         match self.operand_stack.pop().unwrap() {
-            Operand::Register(reg) => reg,
-            Operand::Value(value) => {
+            Operand::Register(reg) => Ok(reg),
+            Operand::Value(value, span) => {
                 let reg = self.register_stack.pop().expect("Ran out of registers");
-                match value {
-                    Value::Int(val) => {
-                        self.bytecode.push(Opcode::LoadInt(reg, val));
-                        Register::new(reg, DataType::Int, true)
-                    }
-                    Value::Float(val) => {
-                        self.bytecode.push(Opcode::LoadFloat(reg, val));
-                        Register::new(reg, DataType::Float, true)
-                    }
-                    Value::Bool(val) => {
-                        self.bytecode.push(Opcode::LoadBool(reg, val));
-                        Register::new(reg, DataType::Bool, true)
-                    }
-                    Value::Str(val) => {
-                        self.bytecode.push(Opcode::LoadStr(reg, val));
-                        Register::new(reg, DataType::Str, true)
-                    }
-                    Value::Char(val) => {
-                        self.bytecode.push(Opcode::LoadChar(reg, val));
-                        Register::new(reg, DataType::Char, true)
-                    }
-                }
+                let data_type = value.data_type();
+                self.compile_load_value(reg, value, span)?;
+                Ok(Register::new(reg, data_type, true))
             }
         }
     }
@@ -310,31 +2267,87 @@ impl Compiler {
         &mut self,
         expression: &Expression,
         target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        let result = self.compile_expression_kind(expression, target_register);
+        self.record_spans(expression.span());
+        result
+    }
+
+    fn compile_expression_kind(
+        &mut self,
+        expression: &Expression,
+        target_register: Option<u8>,
     ) -> Result<(), Error> {
         match expression {
             Expression::Literal(literal) => {
                 self.operand_stack
-                    .push(Operand::Value(literal.value().clone()));
+                    .push(Operand::Value(literal.value().clone(), expression.span()));
                 Ok(())
             }
+            Expression::Interpolation(interpolation) => {
+                self.compile_interpolation(interpolation, target_register)
+            }
             Expression::Identifier(identifier) => {
-                let register = match self.variables.get(identifier.name()) {
-                    Some(register) => register,
-                    None => {
-                        return Err(
-                            self.new_identifier_not_found_error(identifier, expression.span())
-                        );
-                    }
+                if let Some(register) = self.resolve_variable(identifier.name()) {
+                    self.check_assigned(&register, identifier, expression.span())?;
+                    self.operand_stack.push(Operand::Register(register));
+                    return Ok(());
+                }
+                let Some(function) = self.functions.get(identifier.name()) else {
+                    return Err(self.new_identifier_not_found_error(identifier, expression.span()));
+                };
+
+                // A named function used as a value becomes a closure without captures
+                let data_type = DataType::Function(
+                    function.parameters.clone(),
+                    function.return_type.clone().map(Box::new),
+                );
+                let address = function.address;
+                let target_register = match target_register {
+                    Some(reg) => reg,
+                    None => self.register_stack.pop().expect("Ran out of registers"),
                 };
-                self.operand_stack.push(Operand::Register(*register));
+                self.bytecode
+                    .push(Opcode::Closure(target_register, address));
+                self.operand_stack.push(Operand::Register(Register::new(
+                    target_register,
+                    data_type,
+                    true,
+                )));
                 Ok(())
             }
+            Expression::Lambda(lambda) => self.compile_lambda(lambda, target_register),
+            Expression::Cast(cast) => self.compile_cast(cast, target_register),
+            Expression::List(list) => self.compile_list(list, None, target_register),
+            Expression::Map(map) => self.compile_map(map, None, target_register),
+            Expression::Index(index) => self.compile_index(index, target_register),
+            Expression::StructLiteral(struct_literal) => {
+                self.compile_struct_literal(struct_literal, target_register)
+            }
+            Expression::FieldAccess(field_access) => {
+                self.compile_field_access(field_access, target_register)
+            }
+            Expression::BinaryOperation(binop)
+                if matches!(binop.operator(), BinaryOperator::And | BinaryOperator::Or) =>
+            {
+                self.compile_logical_operation(binop, target_register)
+            }
             Expression::BinaryOperation(binop) => {
-                self.compile_expression(binop.left(), target_register)?;
-                self.compile_expression(binop.right(), target_register)?;
+                // Only the final result may go to the target register, otherwise the
+                // operands would overwrite each other before the operation is performed
+                self.compile_expression(binop.left(), None)?;
+                self.compile_expression(binop.right(), None)?;
 
-                let right_register = self.get_register();
-                let left_register = self.get_register();
+                let right_register = self.get_register()?;
+                let left_register = self.get_register()?;
+                let (left_register, right_register) = match self.promote_operands(
+                    binop.operator(),
+                    left_register.clone(),
+                    right_register.clone(),
+                ) {
+                    Some(promoted) => promoted,
+                    None => (left_register, right_register),
+                };
                 let target_register = match target_register {
                     Some(reg) => reg,
                     None => {
@@ -348,627 +2361,23 @@ impl Compiler {
                     }
                 };
 
-                match left_register.data_type {
-                    DataType::Int => match right_register.data_type {
-                        DataType::Int => match binop.operator() {
-                            BinaryOperator::Equal => {
-                                self.bytecode.push(Opcode::EqualInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::NotEqual => {
-                                self.bytecode.push(Opcode::NotEqualInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessThan => {
-                                self.bytecode.push(Opcode::LessThanInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessEq => {
-                                self.bytecode.push(Opcode::LessEqInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterThan => {
-                                self.bytecode.push(Opcode::GreaterThanInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterEq => {
-                                self.bytecode.push(Opcode::GreaterEqInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Add => {
-                                self.bytecode.push(Opcode::AddInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Int,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Sub => {
-                                self.bytecode.push(Opcode::SubInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Int,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Mul => {
-                                self.bytecode.push(Opcode::MulInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Int,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Div => {
-                                self.bytecode.push(Opcode::DivInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Int,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Mod => {
-                                self.bytecode.push(Opcode::ModInt(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Int,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Or | BinaryOperator::And => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        DataType::Str => match binop.operator() {
-                            BinaryOperator::Mul => {
-                                self.bytecode.push(Opcode::MulStr(
-                                    right_register.value,
-                                    left_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Str,
-                                    true,
-                                )));
-                            }
-                            _ => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        _ => {
-                            return Err(self.new_binary_operation_error(
-                                binop,
-                                &right_register,
-                                &left_register,
-                            ));
-                        }
-                    },
-                    DataType::Float => match right_register.data_type {
-                        DataType::Float => match binop.operator() {
-                            BinaryOperator::Equal => {
-                                self.bytecode.push(Opcode::EqualFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::NotEqual => {
-                                self.bytecode.push(Opcode::NotEqualFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessThan => {
-                                self.bytecode.push(Opcode::LessThanFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessEq => {
-                                self.bytecode.push(Opcode::LessEqFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterThan => {
-                                self.bytecode.push(Opcode::GreaterThanFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterEq => {
-                                self.bytecode.push(Opcode::GreaterEqFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Add => {
-                                self.bytecode.push(Opcode::AddFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Float,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Sub => {
-                                self.bytecode.push(Opcode::SubFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Float,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Mul => {
-                                self.bytecode.push(Opcode::MulFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Float,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Div => {
-                                self.bytecode.push(Opcode::DivFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Float,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Mod => {
-                                self.bytecode.push(Opcode::ModFloat(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Float,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Or | BinaryOperator::And => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        _ => {
-                            return Err(self.new_binary_operation_error(
-                                binop,
-                                &right_register,
-                                &left_register,
-                            ));
-                        }
-                    },
-                    DataType::Bool => match right_register.data_type {
-                        DataType::Bool => match binop.operator() {
-                            BinaryOperator::Or => {
-                                self.bytecode.push(Opcode::Or(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::And => {
-                                self.bytecode.push(Opcode::And(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Equal => {
-                                self.bytecode.push(Opcode::EqualBool(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::NotEqual => {
-                                self.bytecode.push(Opcode::NotEqualBool(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessThan
-                            | BinaryOperator::LessEq
-                            | BinaryOperator::GreaterThan
-                            | BinaryOperator::GreaterEq
-                            | BinaryOperator::Add
-                            | BinaryOperator::Sub
-                            | BinaryOperator::Mul
-                            | BinaryOperator::Div
-                            | BinaryOperator::Mod => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        _ => {
-                            return Err(self.new_binary_operation_error(
-                                binop,
-                                &right_register,
-                                &left_register,
-                            ));
-                        }
-                    },
-                    DataType::Str => match right_register.data_type {
-                        DataType::Str => match binop.operator() {
-                            BinaryOperator::Equal => {
-                                self.bytecode.push(Opcode::EqualStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::NotEqual => {
-                                self.bytecode.push(Opcode::NotEqualStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessThan => {
-                                self.bytecode.push(Opcode::LessThanStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessEq => {
-                                self.bytecode.push(Opcode::LessEqStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterThan => {
-                                self.bytecode.push(Opcode::GreaterThanStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterEq => {
-                                self.bytecode.push(Opcode::GreaterEqStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Add => {
-                                self.bytecode.push(Opcode::AddStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Str,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Or
-                            | BinaryOperator::And
-                            | BinaryOperator::Sub
-                            | BinaryOperator::Mul
-                            | BinaryOperator::Div
-                            | BinaryOperator::Mod => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        DataType::Int => match binop.operator() {
-                            BinaryOperator::Mul => {
-                                self.bytecode.push(Opcode::MulStr(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Str,
-                                    true,
-                                )));
-                            }
-                            _ => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        _ => {
-                            return Err(self.new_binary_operation_error(
-                                binop,
-                                &right_register,
-                                &left_register,
-                            ));
-                        }
-                    },
-                    DataType::Char => match right_register.data_type {
-                        DataType::Char => match binop.operator() {
-                            BinaryOperator::Equal => {
-                                self.bytecode.push(Opcode::EqualChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::NotEqual => {
-                                self.bytecode.push(Opcode::NotEqualChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessThan => {
-                                self.bytecode.push(Opcode::LessThanChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::LessEq => {
-                                self.bytecode.push(Opcode::LessEqChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterThan => {
-                                self.bytecode.push(Opcode::GreaterThanChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::GreaterEq => {
-                                self.bytecode.push(Opcode::GreaterEqChar(
-                                    left_register.value,
-                                    right_register.value,
-                                    target_register,
-                                ));
-                                self.operand_stack.push(Operand::Register(Register::new(
-                                    target_register,
-                                    DataType::Bool,
-                                    true,
-                                )));
-                            }
-                            BinaryOperator::Or
-                            | BinaryOperator::And
-                            | BinaryOperator::Add
-                            | BinaryOperator::Sub
-                            | BinaryOperator::Mul
-                            | BinaryOperator::Div
-                            | BinaryOperator::Mod => {
-                                return Err(self.new_binary_operation_error(
-                                    binop,
-                                    &right_register,
-                                    &left_register,
-                                ));
-                            }
-                        },
-                        _ => {
-                            return Err(self.new_binary_operation_error(
-                                binop,
-                                &right_register,
-                                &left_register,
-                            ));
-                        }
-                    },
-                }
+                let Some(data_type) = self.compile_binary_operator(
+                    binop.operator(),
+                    &left_register,
+                    &right_register,
+                    target_register,
+                ) else {
+                    return Err(self.new_binary_operation_error(
+                        binop,
+                        &right_register,
+                        &left_register,
+                    ));
+                };
+                self.operand_stack.push(Operand::Register(Register::new(
+                    target_register,
+                    data_type,
+                    true,
+                )));
 
                 if left_register.is_temporary && left_register.value != target_register {
                     self.register_stack.push(left_register.value);
@@ -979,9 +2388,13 @@ impl Compiler {
 
                 Ok(())
             }
+            Expression::Call(call) => {
+                self.compile_call(call, target_register, true)?;
+                Ok(())
+            }
             Expression::UnaryOperation(unop) => {
                 self.compile_expression(unop.operand(), target_register)?;
-                let register = self.get_register();
+                let register = self.get_register()?;
                 let target_register = match target_register {
                     Some(reg) => reg,
                     None => {
@@ -1004,6 +2417,15 @@ impl Compiler {
                                 true,
                             )));
                         }
+                        UnaryOperator::BitNot => {
+                            self.bytecode
+                                .push(Opcode::BitNotInt(register.value, target_register));
+                            self.operand_stack.push(Operand::Register(Register::new(
+                                target_register,
+                                DataType::Int,
+                                true,
+                            )));
+                        }
                         UnaryOperator::Not => {
                             return Err(self.new_unary_operation_error(unop, &register));
                         }
@@ -1018,7 +2440,7 @@ impl Compiler {
                                 true,
                             )));
                         }
-                        UnaryOperator::Not => {
+                        UnaryOperator::Not | UnaryOperator::BitNot => {
                             return Err(self.new_unary_operation_error(unop, &register));
                         }
                     },
@@ -1032,7 +2454,7 @@ impl Compiler {
                                 true,
                             )));
                         }
-                        UnaryOperator::Neg => {
+                        UnaryOperator::Neg | UnaryOperator::BitNot => {
                             return Err(self.new_unary_operation_error(unop, &register));
                         }
                     },
@@ -1047,6 +2469,589 @@ impl Compiler {
         }
     }
 
+    // 'and' and 'or' skip their right operand if the left one already decides the result
+    fn compile_logical_operation(
+        &mut self,
+        binop: &BinaryOperation,
+        target_register: Option<u8>,
+    ) -> Result<(), Error> {
+        self.compile_expression(binop.left(), None)?;
+        let left_register = self.get_register()?;
+        // The right operand might read the target register, so the result is built in a new one
+        let result_register = if left_register.is_temporary {
+            left_register.value
+        } else {
+            let register = self.register_stack.pop().expect("Ran out of registers");
+            self.bytecode
+                .push(Opcode::Copy(left_register.value, register));
+            register
+        };
+
+        let jump_opcode_idx = self.bytecode.len();
+        // Placeholder to be replaced once the end of the right operand is known
+        self.bytecode.push(Opcode::Error);
+
+        self.compile_expression(binop.right(), Some(result_register))?;
+        let right_register = self.get_register()?;
+        if left_register.data_type != DataType::Bool || right_register.data_type != DataType::Bool {
+            return Err(self.new_binary_operation_error(binop, &right_register, &left_register));
+        }
+        if right_register.value != result_register {
+            self.bytecode
+                .push(Opcode::Copy(right_register.value, result_register));
+            if right_register.is_temporary {
+                self.register_stack.push(right_register.value);
+            }
+        }
+
+        let offset = Self::forward_jump_offset(jump_opcode_idx, self.bytecode.len());
+        self.bytecode[jump_opcode_idx] = match binop.operator() {
+            BinaryOperator::And => Opcode::JumpCond(result_register, offset),
+            _ => Opcode::JumpCondTrue(result_register, offset),
+        };
+
+        let result_register = match target_register {
+            Some(reg) if reg != result_register => {
+                self.bytecode.push(Opcode::Copy(result_register, reg));
+                self.register_stack.push(result_register);
+                reg
+            }
+            _ => result_register,
+        };
+        self.operand_stack.push(Operand::Register(Register::new(
+            result_register,
+            DataType::Bool,
+            true,
+        )));
+        Ok(())
+    }
+
+    // Emits the typed opcode for the operation and returns the type of its result,
+    // or None if the operator is not defined for the operand types
+    fn compile_binary_operator(
+        &mut self,
+        operator: BinaryOperator,
+        left_register: &Register,
+        right_register: &Register,
+        target_register: u8,
+    ) -> Option<DataType> {
+        let data_type = match left_register.data_type {
+            DataType::Int => match right_register.data_type {
+                DataType::Int => match operator {
+                    BinaryOperator::Equal => {
+                        self.bytecode.push(Opcode::EqualInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::NotEqual => {
+                        self.bytecode.push(Opcode::NotEqualInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessThan => {
+                        self.bytecode.push(Opcode::LessThanInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessEq => {
+                        self.bytecode.push(Opcode::LessEqInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterThan => {
+                        self.bytecode.push(Opcode::GreaterThanInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterEq => {
+                        self.bytecode.push(Opcode::GreaterEqInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::Add => {
+                        self.bytecode.push(Opcode::AddInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Sub => {
+                        self.bytecode.push(Opcode::SubInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Mul => {
+                        self.bytecode.push(Opcode::MulInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Div => {
+                        self.bytecode.push(Opcode::DivInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Mod => {
+                        self.bytecode.push(Opcode::ModInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::FloorDiv => {
+                        self.bytecode.push(Opcode::FloorDivInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Pow => {
+                        self.bytecode.push(Opcode::PowInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::BitOr => {
+                        self.bytecode.push(Opcode::BitOrInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::BitXor => {
+                        self.bytecode.push(Opcode::BitXorInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::BitAnd => {
+                        self.bytecode.push(Opcode::BitAndInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::ShiftLeft => {
+                        self.bytecode.push(Opcode::ShiftLeftInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::ShiftRight => {
+                        self.bytecode.push(Opcode::ShiftRightInt(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Int
+                    }
+                    BinaryOperator::Or | BinaryOperator::And => {
+                        return None;
+                    }
+                },
+                DataType::Str => match operator {
+                    BinaryOperator::Mul => {
+                        self.bytecode.push(Opcode::MulStr(
+                            right_register.value,
+                            left_register.value,
+                            target_register,
+                        ));
+                        DataType::Str
+                    }
+                    _ => {
+                        return None;
+                    }
+                },
+                _ => {
+                    return None;
+                }
+            },
+            DataType::Float => match right_register.data_type {
+                DataType::Float => match operator {
+                    BinaryOperator::Equal => {
+                        self.bytecode.push(Opcode::EqualFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::NotEqual => {
+                        self.bytecode.push(Opcode::NotEqualFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessThan => {
+                        self.bytecode.push(Opcode::LessThanFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessEq => {
+                        self.bytecode.push(Opcode::LessEqFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterThan => {
+                        self.bytecode.push(Opcode::GreaterThanFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterEq => {
+                        self.bytecode.push(Opcode::GreaterEqFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::Add => {
+                        self.bytecode.push(Opcode::AddFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Sub => {
+                        self.bytecode.push(Opcode::SubFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Mul => {
+                        self.bytecode.push(Opcode::MulFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Div => {
+                        self.bytecode.push(Opcode::DivFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Mod => {
+                        self.bytecode.push(Opcode::ModFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::FloorDiv => {
+                        self.bytecode.push(Opcode::FloorDivFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Pow => {
+                        self.bytecode.push(Opcode::PowFloat(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Float
+                    }
+                    BinaryOperator::Or
+                    | BinaryOperator::And
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => {
+                        return None;
+                    }
+                },
+                _ => {
+                    return None;
+                }
+            },
+            DataType::Bool => match right_register.data_type {
+                DataType::Bool => match operator {
+                    BinaryOperator::Or => {
+                        self.bytecode.push(Opcode::Or(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::And => {
+                        self.bytecode.push(Opcode::And(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::Equal => {
+                        self.bytecode.push(Opcode::EqualBool(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::NotEqual => {
+                        self.bytecode.push(Opcode::NotEqualBool(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessThan
+                    | BinaryOperator::LessEq
+                    | BinaryOperator::GreaterThan
+                    | BinaryOperator::GreaterEq
+                    | BinaryOperator::Add
+                    | BinaryOperator::Sub
+                    | BinaryOperator::Mul
+                    | BinaryOperator::Div
+                    | BinaryOperator::FloorDiv
+                    | BinaryOperator::Mod
+                    | BinaryOperator::Pow
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => {
+                        return None;
+                    }
+                },
+                _ => {
+                    return None;
+                }
+            },
+            DataType::Str => match right_register.data_type {
+                DataType::Str => match operator {
+                    BinaryOperator::Equal => {
+                        self.bytecode.push(Opcode::EqualStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::NotEqual => {
+                        self.bytecode.push(Opcode::NotEqualStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessThan => {
+                        self.bytecode.push(Opcode::LessThanStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessEq => {
+                        self.bytecode.push(Opcode::LessEqStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterThan => {
+                        self.bytecode.push(Opcode::GreaterThanStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterEq => {
+                        self.bytecode.push(Opcode::GreaterEqStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::Add => {
+                        self.bytecode.push(Opcode::AddStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Str
+                    }
+                    BinaryOperator::Or
+                    | BinaryOperator::And
+                    | BinaryOperator::Sub
+                    | BinaryOperator::Mul
+                    | BinaryOperator::Div
+                    | BinaryOperator::FloorDiv
+                    | BinaryOperator::Mod
+                    | BinaryOperator::Pow
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => {
+                        return None;
+                    }
+                },
+                DataType::Int => match operator {
+                    BinaryOperator::Mul => {
+                        self.bytecode.push(Opcode::MulStr(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Str
+                    }
+                    _ => {
+                        return None;
+                    }
+                },
+                _ => {
+                    return None;
+                }
+            },
+            DataType::Char => match right_register.data_type {
+                DataType::Char => match operator {
+                    BinaryOperator::Equal => {
+                        self.bytecode.push(Opcode::EqualChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::NotEqual => {
+                        self.bytecode.push(Opcode::NotEqualChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessThan => {
+                        self.bytecode.push(Opcode::LessThanChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::LessEq => {
+                        self.bytecode.push(Opcode::LessEqChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterThan => {
+                        self.bytecode.push(Opcode::GreaterThanChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::GreaterEq => {
+                        self.bytecode.push(Opcode::GreaterEqChar(
+                            left_register.value,
+                            right_register.value,
+                            target_register,
+                        ));
+                        DataType::Bool
+                    }
+                    BinaryOperator::Or
+                    | BinaryOperator::And
+                    | BinaryOperator::Add
+                    | BinaryOperator::Sub
+                    | BinaryOperator::Mul
+                    | BinaryOperator::Div
+                    | BinaryOperator::FloorDiv
+                    | BinaryOperator::Mod
+                    | BinaryOperator::Pow
+                    | BinaryOperator::BitOr
+                    | BinaryOperator::BitXor
+                    | BinaryOperator::BitAnd
+                    | BinaryOperator::ShiftLeft
+                    | BinaryOperator::ShiftRight => {
+                        return None;
+                    }
+                },
+                _ => {
+                    return None;
+                }
+            },
+            DataType::Function(..)
+            | DataType::List(_)
+            | DataType::Map(..)
+            | DataType::Struct(_) => {
+                return None;
+            }
+        };
+        Some(data_type)
+    }
+
     #[inline]
     fn new_binary_operation_error(
         &self,
@@ -1078,17 +3083,44 @@ impl Compiler {
         )
     }
 
+    #[inline]
+    fn new_not_callable_error(&self, call: &Call, data_type: &DataType) -> Error {
+        Error::new(
+            self.filename.clone(),
+            self.source_code.clone(),
+            ErrorKind::NotCallable(data_type.typename()),
+            call.span(),
+            call.callee().span(),
+        )
+    }
+
     #[inline]
     fn new_identifier_not_found_error(&self, identifier: &Identifier, context: Span) -> Error {
+        let kind = if self.closed_variables.contains(identifier.name()) {
+            ErrorKind::IdentifierOutOfScope
+        } else {
+            ErrorKind::IdentifierNotFound
+        };
         Error::new(
             self.filename.clone(),
             self.source_code.clone(),
-            ErrorKind::IdentifierNotFound,
+            kind,
             context,
             identifier.span(),
         )
     }
 
+    #[inline]
+    fn new_outside_loop_error(&self, keyword: &str, span: Span) -> Error {
+        Error::new(
+            self.filename.clone(),
+            self.source_code.clone(),
+            ErrorKind::StatementOutsideLoop(keyword.to_owned()),
+            span,
+            span,
+        )
+    }
+
     #[inline]
     fn new_invalid_assignment_error(
         &self,
@@ -1113,6 +3145,6 @@ impl Compiler {
                 return true;
             }
         }
-        return false;
+        false
     }
 }
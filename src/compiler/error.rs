@@ -1,13 +1,43 @@
 use super::language_components::{BinaryOperator, Span, UnaryOperator};
+use crate::interpreter::RuntimeErrorKind;
 
 #[derive(Debug, Clone)]
 pub enum ErrorKind {
     InvalidBinaryOperation(BinaryOperator, String, String),
     InvalidUnaryOperation(UnaryOperator, String),
     IdentifierNotFound,
+    IdentifierOutOfScope,
+    IdentifierNotAssigned,
     IdentifierIsKeyword,
     InvalidAssignment(String, String),
     ArgumentInvalidType(String, String),
+    StatementOutsideLoop(String),
+    IdentifierAlreadyDefined,
+    NotCallable(String),
+    ArgumentCountMismatch(usize, usize),
+    MissingReturnValue,
+    NoReturnValue(String),
+    MissingReturn(String, String),
+    InvalidCast(String, String),
+    NotIndexable(String),
+    ExpectedList(String),
+    ExpectedMap(String),
+    ExpectedCollection(String),
+    UnknownListType,
+    UnknownMapType,
+    InvalidMapKey(String),
+    UnknownType(String),
+    UnknownField(String, String),
+    MissingField(String, String),
+    DuplicateField(String),
+    NoFields(String),
+    TooManyFields(String),
+    InvalidEscape(String),
+    IntegerOutOfRange,
+    TooManyConstants,
+    LegacyComment,
+    // Raised while running the program, located through the source map
+    Runtime(RuntimeErrorKind),
 }
 
 impl std::fmt::Display for ErrorKind {
@@ -28,6 +58,12 @@ impl std::fmt::Display for ErrorKind {
                 )
             }
             ErrorKind::IdentifierNotFound => write!(f, "Identifier not found"),
+            ErrorKind::IdentifierOutOfScope => {
+                write!(f, "Identifier is not in scope anymore")
+            }
+            ErrorKind::IdentifierNotAssigned => {
+                write!(f, "Identifier is used before being assigned a value")
+            }
             ErrorKind::IdentifierIsKeyword => write!(f, "Expected identifier, found keyword"),
             ErrorKind::InvalidAssignment(t1, t2) => {
                 write!(
@@ -39,6 +75,84 @@ impl std::fmt::Display for ErrorKind {
             ErrorKind::ArgumentInvalidType(t1, t2) => {
                 write!(f, "Expected type '{}', found type '{}'", t1, t2)
             }
+            ErrorKind::StatementOutsideLoop(keyword) => {
+                write!(f, "'{}' can only be used inside a loop", keyword)
+            }
+            ErrorKind::IdentifierAlreadyDefined => write!(f, "Identifier is already defined"),
+            ErrorKind::NotCallable(typename) => {
+                write!(f, "Value of type '{}' is not callable", typename)
+            }
+            ErrorKind::ArgumentCountMismatch(expected, found) => {
+                write!(f, "Expected {} arguments, found {}", expected, found)
+            }
+            ErrorKind::MissingReturnValue => write!(f, "Expected a return value"),
+            ErrorKind::NoReturnValue(name) => {
+                write!(f, "Function '{}' does not return a value", name)
+            }
+            ErrorKind::MissingReturn(name, typename) => {
+                write!(
+                    f,
+                    "Function '{}' does not return a value of type '{}' on every path",
+                    name, typename
+                )
+            }
+            ErrorKind::InvalidCast(from, to) => {
+                write!(f, "Cannot cast a value of type '{}' to type '{}'", from, to)
+            }
+            ErrorKind::NotIndexable(typename) => {
+                write!(f, "Value of type '{}' cannot be indexed", typename)
+            }
+            ErrorKind::ExpectedList(typename) => {
+                write!(f, "Expected a list, found type '{}'", typename)
+            }
+            ErrorKind::ExpectedMap(typename) => {
+                write!(f, "Expected a map, found type '{}'", typename)
+            }
+            ErrorKind::ExpectedCollection(typename) => {
+                write!(f, "Expected a list or map, found type '{}'", typename)
+            }
+            ErrorKind::UnknownListType => {
+                write!(f, "Cannot infer the element type of an empty list")
+            }
+            ErrorKind::UnknownMapType => {
+                write!(f, "Cannot infer the key and value types of an empty map")
+            }
+            ErrorKind::InvalidMapKey(typename) => {
+                write!(f, "Type '{}' cannot be used as a map key", typename)
+            }
+            ErrorKind::UnknownType(name) => write!(f, "Unknown type '{}'", name),
+            ErrorKind::UnknownField(struct_name, field) => {
+                write!(f, "Struct '{}' has no field '{}'", struct_name, field)
+            }
+            ErrorKind::MissingField(struct_name, field) => {
+                write!(f, "Missing field '{}' of struct '{}'", field, struct_name)
+            }
+            ErrorKind::DuplicateField(field) => {
+                write!(f, "Field '{}' is specified more than once", field)
+            }
+            ErrorKind::NoFields(typename) => {
+                write!(f, "Value of type '{}' has no fields", typename)
+            }
+            ErrorKind::TooManyFields(struct_name) => {
+                write!(f, "Struct '{}' has more than 255 fields", struct_name)
+            }
+            ErrorKind::InvalidEscape(escape) => {
+                write!(f, "Invalid escape sequence '{}'", escape)
+            }
+            ErrorKind::IntegerOutOfRange => write!(f, "Integer literal out of range"),
+            ErrorKind::LegacyComment => {
+                write!(
+                    f,
+                    "Line comments start with '#', '//' is the floor division operator"
+                )
+            }
+            ErrorKind::TooManyConstants => {
+                write!(
+                    f,
+                    "Too many distinct constants, at most 65536 are supported"
+                )
+            }
+            ErrorKind::Runtime(kind) => write!(f, "{}", kind),
         }
     }
 }
@@ -47,7 +161,8 @@ impl std::fmt::Display for ErrorKind {
 pub struct Error {
     filename: String,
     source_code: String,
-    kind: ErrorKind,
+    // Boxed to keep Result<_, Error> small, errors are rare compared to successful results
+    kind: Box<ErrorKind>,
     context: Span,
     error: Span,
 }
@@ -63,7 +178,7 @@ impl Error {
         Error {
             filename,
             source_code,
-            kind,
+            kind: Box::new(kind),
             context,
             error,
         }
@@ -94,7 +209,7 @@ impl Error {
 
         // Print '^' characters under the error range, even if the error range has newlines in it
         // Print the whole line which contains the context_end
-        while let Some((idx, ch)) = iter.next() {
+        for (idx, ch) in iter {
             if ch == '\n' {
                 if idx >= self.context.end() {
                     break;
@@ -119,7 +234,7 @@ impl Error {
 
         result.push('\n');
         result.push_str(&buffer);
-        result.push_str(format!("\n\nError: {}", self.kind.to_string()).as_str());
+        result.push_str(format!("\n\nError: {}", self.kind).as_str());
         result
     }
 
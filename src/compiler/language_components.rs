@@ -17,22 +17,196 @@ impl FunctionBody {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct Block {
+    control_flow_structures: Vec<ControlFlow>,
+}
+
+impl Block {
+    #[inline]
+    pub fn new(logic: Vec<ControlFlow>) -> Block {
+        Block {
+            control_flow_structures: logic,
+        }
+    }
+
+    #[inline]
+    pub fn control_flow_structures(&self) -> &Vec<ControlFlow> {
+        &self.control_flow_structures
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ControlFlow {
     BasicBlock(BasicBlock),
     WhileLoop(WhileLoop),
+    If(IfStatement),
+    FunctionDeclaration(FunctionDeclaration),
+    StructDeclaration(StructDeclaration),
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionDeclaration {
+    span: Span,
+    identifier: Identifier,
+    parameters: Vec<Parameter>,
+    return_type: Option<DataType>,
+    body: Block,
+}
+
+impl FunctionDeclaration {
+    #[inline]
+    pub fn new(
+        span: Span,
+        identifier: Identifier,
+        parameters: Vec<Parameter>,
+        return_type: Option<DataType>,
+        body: Block,
+    ) -> FunctionDeclaration {
+        FunctionDeclaration {
+            span,
+            identifier,
+            parameters,
+            return_type,
+            body,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> &Identifier {
+        &self.identifier
+    }
+
+    #[inline]
+    pub fn parameters(&self) -> &Vec<Parameter> {
+        &self.parameters
+    }
+
+    #[inline]
+    pub fn return_type(&self) -> Option<&DataType> {
+        self.return_type.as_ref()
+    }
+
+    #[inline]
+    pub fn body(&self) -> &Block {
+        &self.body
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Parameter {
+    span: Span,
+    identifier: Identifier,
+    data_type: DataType,
+}
+
+impl Parameter {
+    #[inline]
+    pub fn new(span: Span, identifier: Identifier, data_type: DataType) -> Parameter {
+        Parameter {
+            span,
+            identifier,
+            data_type,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> &Identifier {
+        &self.identifier
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StructDeclaration {
+    span: Span,
+    identifier: Identifier,
+    fields: Vec<StructField>,
+}
+
+impl StructDeclaration {
+    #[inline]
+    pub fn new(span: Span, identifier: Identifier, fields: Vec<StructField>) -> StructDeclaration {
+        StructDeclaration {
+            span,
+            identifier,
+            fields,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> &Identifier {
+        &self.identifier
+    }
+
+    #[inline]
+    pub fn fields(&self) -> &Vec<StructField> {
+        &self.fields
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StructField {
+    span: Span,
+    identifier: Identifier,
+    data_type: DataType,
+}
+
+impl StructField {
+    #[inline]
+    pub fn new(span: Span, identifier: Identifier, data_type: DataType) -> StructField {
+        StructField {
+            span,
+            identifier,
+            data_type,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> &Identifier {
+        &self.identifier
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct WhileLoop {
     span: Span,
     condition: Expression,
-    body: BasicBlock,
+    body: Block,
 }
 
 impl WhileLoop {
     #[inline]
-    pub fn new(span: Span, condition: Expression, body: BasicBlock) -> WhileLoop {
+    pub fn new(span: Span, condition: Expression, body: Block) -> WhileLoop {
         WhileLoop {
             span,
             condition,
@@ -51,7 +225,66 @@ impl WhileLoop {
     }
 
     #[inline]
-    pub fn body(&self) -> &BasicBlock {
+    pub fn body(&self) -> &Block {
+        &self.body
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IfStatement {
+    branches: Vec<ConditionalBranch>,
+    else_body: Option<Block>,
+}
+
+impl IfStatement {
+    #[inline]
+    pub fn new(branches: Vec<ConditionalBranch>, else_body: Option<Block>) -> IfStatement {
+        IfStatement {
+            branches,
+            else_body,
+        }
+    }
+
+    #[inline]
+    pub fn branches(&self) -> &Vec<ConditionalBranch> {
+        &self.branches
+    }
+
+    #[inline]
+    pub fn else_body(&self) -> Option<&Block> {
+        self.else_body.as_ref()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConditionalBranch {
+    span: Span,
+    condition: Expression,
+    body: Block,
+}
+
+impl ConditionalBranch {
+    #[inline]
+    pub fn new(span: Span, condition: Expression, body: Block) -> ConditionalBranch {
+        ConditionalBranch {
+            span,
+            condition,
+            body,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn condition(&self) -> &Expression {
+        &self.condition
+    }
+
+    #[inline]
+    pub fn body(&self) -> &Block {
         &self.body
     }
 }
@@ -78,16 +311,32 @@ pub enum Statement {
     LetStatement(LetStatement),
     Assignment(Assignment),
     ReturnStatement(ReturnStatement),
+    Break(Span),
+    Continue(Span),
     Expression(Expression),
 }
 
+impl Statement {
+    #[inline]
+    pub fn span(&self) -> Span {
+        match self {
+            Statement::LetStatement(let_statement) => let_statement.span(),
+            Statement::Assignment(assignment) => assignment.span(),
+            Statement::ReturnStatement(return_statement) => return_statement.span(),
+            Statement::Break(span) | Statement::Continue(span) => *span,
+            Statement::Expression(expression) => expression.span(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LetStatement {
     span: Span,
     identifier: Identifier,
+    type_annotation: Option<TypeAnnotation>,
     #[allow(dead_code)]
-    operator_span: Span,
-    expression: Box<Expression>,
+    operator_span: Option<Span>,
+    expression: Option<Box<Expression>>,
 }
 
 impl LetStatement {
@@ -95,12 +344,14 @@ impl LetStatement {
     pub fn new(
         span: Span,
         identifier: Identifier,
-        #[allow(dead_code)] operator_span: Span,
-        expression: Box<Expression>,
+        type_annotation: Option<TypeAnnotation>,
+        #[allow(dead_code)] operator_span: Option<Span>,
+        expression: Option<Box<Expression>>,
     ) -> Self {
         LetStatement {
             span,
             identifier,
+            type_annotation,
             operator_span,
             expression,
         }
@@ -116,15 +367,43 @@ impl LetStatement {
         &self.identifier
     }
 
+    #[inline]
+    pub fn type_annotation(&self) -> Option<&TypeAnnotation> {
+        self.type_annotation.as_ref()
+    }
+
     #[allow(dead_code)]
     #[inline]
-    pub fn operator_span(&self) -> Span {
+    pub fn operator_span(&self) -> Option<Span> {
         self.operator_span
     }
 
     #[inline]
-    pub fn expression(&self) -> &Expression {
-        &self.expression
+    pub fn expression(&self) -> Option<&Expression> {
+        self.expression.as_deref()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TypeAnnotation {
+    span: Span,
+    data_type: DataType,
+}
+
+impl TypeAnnotation {
+    #[inline]
+    pub fn new(span: Span, data_type: DataType) -> TypeAnnotation {
+        TypeAnnotation { span, data_type }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
     }
 }
 
@@ -133,6 +412,7 @@ impl LetStatement {
 pub struct Assignment {
     span: Span,
     lhs: Identifier,
+    accessors: Vec<Accessor>,
     operator: AssignmentOperator,
     operator_span: Span,
     rhs: Box<Expression>,
@@ -144,6 +424,7 @@ impl Assignment {
     pub fn new(
         span: Span,
         lhs: Identifier,
+        accessors: Vec<Accessor>,
         operator: AssignmentOperator,
         operator_span: Span,
         rhs: Box<Expression>,
@@ -151,6 +432,7 @@ impl Assignment {
         Assignment {
             span,
             lhs,
+            accessors,
             operator,
             operator_span,
             rhs,
@@ -167,6 +449,12 @@ impl Assignment {
         &self.lhs
     }
 
+    // Indices and fields applied to the variable in order, e.g. 'xs[i].y = v'
+    #[inline]
+    pub fn accessors(&self) -> &Vec<Accessor> {
+        &self.accessors
+    }
+
     #[inline]
     pub fn operator(&self) -> AssignmentOperator {
         self.operator
@@ -183,6 +471,12 @@ impl Assignment {
     }
 }
 
+#[derive(Clone, Debug)]
+pub enum Accessor {
+    Index(Expression),
+    Field(Identifier),
+}
+
 #[allow(dead_code)]
 #[derive(Clone, Copy, Debug)]
 pub enum AssignmentOperator {
@@ -194,37 +488,59 @@ pub enum AssignmentOperator {
     Mod,
 }
 
+impl AssignmentOperator {
+    // The operation performed by a compound assignment, e.g. '+' for '+='
+    #[inline]
+    pub fn binary_operator(&self) -> Option<BinaryOperator> {
+        match self {
+            AssignmentOperator::Basic => None,
+            AssignmentOperator::Add => Some(BinaryOperator::Add),
+            AssignmentOperator::Sub => Some(BinaryOperator::Sub),
+            AssignmentOperator::Mul => Some(BinaryOperator::Mul),
+            AssignmentOperator::Div => Some(BinaryOperator::Div),
+            AssignmentOperator::Mod => Some(BinaryOperator::Mod),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ReturnStatement {
-    #[allow(dead_code)]
     span: Span,
-    expression: Box<Expression>,
+    expression: Option<Box<Expression>>,
 }
 
 impl ReturnStatement {
     #[inline]
-    pub fn new(span: Span, expression: Box<Expression>) -> ReturnStatement {
+    pub fn new(span: Span, expression: Option<Box<Expression>>) -> ReturnStatement {
         ReturnStatement { span, expression }
     }
 
-    #[allow(dead_code)]
     #[inline]
     pub fn span(&self) -> Span {
         self.span
     }
 
     #[inline]
-    pub fn expression(&self) -> &Expression {
-        &self.expression
+    pub fn expression(&self) -> Option<&Expression> {
+        self.expression.as_deref()
     }
 }
 
 #[derive(Clone, Debug)]
 pub enum Expression {
     Literal(Literal),
+    Interpolation(Interpolation),
     Identifier(Identifier),
     BinaryOperation(BinaryOperation),
     UnaryOperation(UnaryOperation),
+    Call(Call),
+    Lambda(Lambda),
+    Cast(Cast),
+    List(List),
+    Map(Map),
+    Index(Index),
+    StructLiteral(StructLiteral),
+    FieldAccess(FieldAccess),
 }
 
 impl Expression {
@@ -232,11 +548,330 @@ impl Expression {
     pub fn span(&self) -> Span {
         match self {
             Expression::Literal(literal) => literal.span(),
+            Expression::Interpolation(interpolation) => interpolation.span(),
             Expression::Identifier(identifier) => identifier.span(),
             Expression::BinaryOperation(binop) => binop.span(),
             Expression::UnaryOperation(unop) => unop.span(),
+            Expression::Call(call) => call.span(),
+            Expression::Lambda(lambda) => lambda.span(),
+            Expression::Cast(cast) => cast.span(),
+            Expression::List(list) => list.span(),
+            Expression::Map(map) => map.span(),
+            Expression::Index(index) => index.span(),
+            Expression::StructLiteral(struct_literal) => struct_literal.span(),
+            Expression::FieldAccess(field_access) => field_access.span(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Lambda {
+    span: Span,
+    parameters: Vec<Parameter>,
+    return_type: Option<DataType>,
+    body: Block,
+}
+
+impl Lambda {
+    #[inline]
+    pub fn new(
+        span: Span,
+        parameters: Vec<Parameter>,
+        return_type: Option<DataType>,
+        body: Block,
+    ) -> Lambda {
+        Lambda {
+            span,
+            parameters,
+            return_type,
+            body,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn parameters(&self) -> &Vec<Parameter> {
+        &self.parameters
+    }
+
+    #[inline]
+    pub fn return_type(&self) -> Option<&DataType> {
+        self.return_type.as_ref()
+    }
+
+    #[inline]
+    pub fn body(&self) -> &Block {
+        &self.body
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> DataType {
+        DataType::Function(
+            self.parameters
+                .iter()
+                .map(|parameter| parameter.data_type().clone())
+                .collect(),
+            self.return_type.clone().map(Box::new),
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Call {
+    span: Span,
+    callee: Box<Expression>,
+    arguments: Vec<Expression>,
+}
+
+impl Call {
+    #[inline]
+    pub fn new(span: Span, callee: Box<Expression>, arguments: Vec<Expression>) -> Call {
+        Call {
+            span,
+            callee,
+            arguments,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn callee(&self) -> &Expression {
+        &self.callee
+    }
+
+    #[inline]
+    pub fn arguments(&self) -> &Vec<Expression> {
+        &self.arguments
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Interpolation {
+    span: Span,
+    parts: Vec<InterpolationPart>,
+}
+
+impl Interpolation {
+    #[inline]
+    pub fn new(span: Span, parts: Vec<InterpolationPart>) -> Interpolation {
+        Interpolation { span, parts }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn parts(&self) -> &Vec<InterpolationPart> {
+        &self.parts
+    }
+}
+
+// Text is kept apart from the embedded expressions, which might be string literals themselves
+#[derive(Clone, Debug)]
+pub enum InterpolationPart {
+    Text(String),
+    Expression(Expression),
+}
+
+#[derive(Clone, Debug)]
+pub struct List {
+    span: Span,
+    elements: Vec<Expression>,
+}
+
+impl List {
+    #[inline]
+    pub fn new(span: Span, elements: Vec<Expression>) -> List {
+        List { span, elements }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn elements(&self) -> &Vec<Expression> {
+        &self.elements
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Map {
+    span: Span,
+    entries: Vec<(Expression, Expression)>,
+}
+
+impl Map {
+    #[inline]
+    pub fn new(span: Span, entries: Vec<(Expression, Expression)>) -> Map {
+        Map { span, entries }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    // Key and value expressions in the order they were written
+    #[inline]
+    pub fn entries(&self) -> &Vec<(Expression, Expression)> {
+        &self.entries
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Index {
+    span: Span,
+    target: Box<Expression>,
+    index: Box<Expression>,
+}
+
+impl Index {
+    #[inline]
+    pub fn new(span: Span, target: Box<Expression>, index: Box<Expression>) -> Index {
+        Index {
+            span,
+            target,
+            index,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn target(&self) -> &Expression {
+        &self.target
+    }
+
+    #[inline]
+    pub fn index(&self) -> &Expression {
+        &self.index
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StructLiteral {
+    span: Span,
+    identifier: Identifier,
+    fields: Vec<(Identifier, Expression)>,
+}
+
+impl StructLiteral {
+    #[inline]
+    pub fn new(
+        span: Span,
+        identifier: Identifier,
+        fields: Vec<(Identifier, Expression)>,
+    ) -> StructLiteral {
+        StructLiteral {
+            span,
+            identifier,
+            fields,
         }
     }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn identifier(&self) -> &Identifier {
+        &self.identifier
+    }
+
+    // Field initializers in the order they were written
+    #[inline]
+    pub fn fields(&self) -> &Vec<(Identifier, Expression)> {
+        &self.fields
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FieldAccess {
+    span: Span,
+    target: Box<Expression>,
+    field: Identifier,
+}
+
+impl FieldAccess {
+    #[inline]
+    pub fn new(span: Span, target: Box<Expression>, field: Identifier) -> FieldAccess {
+        FieldAccess {
+            span,
+            target,
+            field,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn target(&self) -> &Expression {
+        &self.target
+    }
+
+    #[inline]
+    pub fn field(&self) -> &Identifier {
+        &self.field
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cast {
+    span: Span,
+    operand: Box<Expression>,
+    type_span: Span,
+    data_type: DataType,
+}
+
+impl Cast {
+    #[inline]
+    pub fn new(span: Span, operand: Box<Expression>, type_span: Span, data_type: DataType) -> Cast {
+        Cast {
+            span,
+            operand,
+            type_span,
+            data_type,
+        }
+    }
+
+    #[inline]
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    #[inline]
+    pub fn operand(&self) -> &Expression {
+        &self.operand
+    }
+
+    #[inline]
+    pub fn type_span(&self) -> Span {
+        self.type_span
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -302,11 +937,18 @@ pub enum BinaryOperator {
     LessEq,
     GreaterThan,
     GreaterEq,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
     Add,
     Sub,
     Mul,
     Div,
+    FloorDiv,
     Mod,
+    Pow,
 }
 
 impl std::fmt::Display for BinaryOperator {
@@ -320,11 +962,18 @@ impl std::fmt::Display for BinaryOperator {
             BinaryOperator::LessEq => write!(f, "<="),
             BinaryOperator::GreaterThan => write!(f, ">"),
             BinaryOperator::GreaterEq => write!(f, ">="),
+            BinaryOperator::BitOr => write!(f, "|"),
+            BinaryOperator::BitXor => write!(f, "^"),
+            BinaryOperator::BitAnd => write!(f, "&"),
+            BinaryOperator::ShiftLeft => write!(f, "<<"),
+            BinaryOperator::ShiftRight => write!(f, ">>"),
             BinaryOperator::Add => write!(f, "+"),
             BinaryOperator::Sub => write!(f, "-"),
             BinaryOperator::Mul => write!(f, "*"),
             BinaryOperator::Div => write!(f, "/"),
+            BinaryOperator::FloorDiv => write!(f, "//"),
             BinaryOperator::Mod => write!(f, "%"),
+            BinaryOperator::Pow => write!(f, "**"),
         }
     }
 }
@@ -377,6 +1026,7 @@ impl UnaryOperation {
 pub enum UnaryOperator {
     Not,
     Neg,
+    BitNot,
 }
 
 impl std::fmt::Display for UnaryOperator {
@@ -384,6 +1034,7 @@ impl std::fmt::Display for UnaryOperator {
         match self {
             UnaryOperator::Not => write!(f, "not"),
             UnaryOperator::Neg => write!(f, "-"),
+            UnaryOperator::BitNot => write!(f, "~"),
         }
     }
 }
@@ -457,13 +1108,17 @@ impl Span {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum DataType {
     Int,
     Float,
     Bool,
     Str,
     Char,
+    Function(Vec<DataType>, Option<Box<DataType>>), // parameter types, return type
+    List(Box<DataType>),                            // element type
+    Map(Box<DataType>, Box<DataType>),              // key type, value type
+    Struct(String),                                 // name of the declared struct
 }
 
 impl DataType {
@@ -474,6 +1129,24 @@ impl DataType {
             DataType::Bool => "bool".to_string(),
             DataType::Str => "string".to_string(),
             DataType::Char => "char".to_string(),
+            DataType::Function(parameters, return_type) => {
+                let parameters: Vec<String> = parameters.iter().map(|p| p.typename()).collect();
+                match return_type {
+                    Some(return_type) => {
+                        format!(
+                            "fn({}) -> {}",
+                            parameters.join(", "),
+                            return_type.typename()
+                        )
+                    }
+                    None => format!("fn({})", parameters.join(", ")),
+                }
+            }
+            DataType::List(element_type) => format!("[{}]", element_type.typename()),
+            DataType::Map(key_type, value_type) => {
+                format!("{{{}: {}}}", key_type.typename(), value_type.typename())
+            }
+            DataType::Struct(name) => name.clone(),
         }
     }
 }
@@ -483,6 +1156,8 @@ pub enum Value {
     Int(i64),
     Float(f64),
     Bool(bool),
+    // Boxed to keep literals small
+    #[allow(clippy::box_collection)]
     Str(Box<String>),
     Char(char),
 }
@@ -1,87 +1,272 @@
+use super::error::{Error, ErrorKind};
 use super::language_components::*;
 use pest::{Parser, iterators::Pair};
 use pest_derive::Parser;
 
-const MAX_BINARY_PRECEDENCE_DEPTH: u8 = 6;
-const MAX_PRECEDENCE_DEPTH: u8 = 7;
+const MAX_BINARY_PRECEDENCE_DEPTH: u8 = 10;
+const CAST_PRECEDENCE_DEPTH: u8 = 10;
+const UNARY_PRECEDENCE_DEPTH: u8 = 11;
+const POWER_PRECEDENCE_DEPTH: u8 = 12;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 struct MyParser;
 
-pub fn parse(input: &str) -> Result<FunctionBody, String> {
-    let parse_result = MyParser::parse(Rule::start_symbol, &input);
+struct ParserState<'a> {
+    filename: &'a str,
+    source_code: &'a str,
+}
+
+impl ParserState<'_> {
+    #[inline]
+    fn new_error(&self, kind: ErrorKind, context: Span, error: Span) -> Error {
+        Error::new(
+            self.filename.to_owned(),
+            self.source_code.to_owned(),
+            kind,
+            context,
+            error,
+        )
+    }
+}
+
+pub fn parse(input: &str, filename: &str) -> Result<FunctionBody, String> {
+    let parse_result = MyParser::parse(Rule::start_symbol, input);
     match parse_result {
         Ok(mut parse_content) => {
             let function_body_pair = parse_content.next().unwrap().into_inner().next().unwrap();
-            Ok(parse_function_body(function_body_pair))
+            let mut state = ParserState {
+                filename,
+                source_code: input,
+            };
+            parse_function_body(function_body_pair, &mut state).map_err(|e| e.to_string())
         }
         Err(e) => Err(e.to_string()),
     }
 }
 
-fn parse_function_body(pair: Pair<Rule>) -> FunctionBody {
+fn parse_function_body(pair: Pair<Rule>, state: &mut ParserState) -> Result<FunctionBody, Error> {
     let mut result: Vec<ControlFlow> = Vec::new();
-    let mut inner_rules = pair.into_inner();
-    while let Some(control_flow_pair) = inner_rules.next() {
-        result.push(parse_control_flow(control_flow_pair));
+    for control_flow_pair in pair.into_inner() {
+        result.push(parse_control_flow(control_flow_pair, state)?);
     }
-    FunctionBody::new(result)
+    Ok(FunctionBody::new(result))
 }
 
-fn parse_control_flow(pair: Pair<Rule>) -> ControlFlow {
+fn parse_control_flow(pair: Pair<Rule>, state: &mut ParserState) -> Result<ControlFlow, Error> {
     let control_flow_pair = pair.into_inner().next().unwrap();
-    match control_flow_pair.as_rule() {
-        Rule::while_loop => ControlFlow::WhileLoop(parse_while_loop(control_flow_pair)),
-        Rule::basic_block => ControlFlow::BasicBlock(parse_basic_block(control_flow_pair)),
+    let control_flow = match control_flow_pair.as_rule() {
+        Rule::function_declaration => {
+            ControlFlow::FunctionDeclaration(parse_function_declaration(control_flow_pair, state)?)
+        }
+        Rule::struct_declaration => {
+            ControlFlow::StructDeclaration(parse_struct_declaration(control_flow_pair))
+        }
+        Rule::while_loop => ControlFlow::WhileLoop(parse_while_loop(control_flow_pair, state)?),
+        Rule::if_statement => ControlFlow::If(parse_if_statement(control_flow_pair, state)?),
+        Rule::basic_block => ControlFlow::BasicBlock(parse_basic_block(control_flow_pair, state)?),
         _ => unreachable!(),
+    };
+    Ok(control_flow)
+}
+
+fn parse_function_declaration(
+    pair: Pair<Rule>,
+    state: &mut ParserState,
+) -> Result<FunctionDeclaration, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let mut identifier: Option<Identifier> = None;
+    let mut parameters: Vec<Parameter> = Vec::new();
+    let mut return_type: Option<DataType> = None;
+    let mut body: Option<Block> = None;
+
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::identifier => identifier = Some(parse_identifier(pair)),
+            Rule::parameter => parameters.push(parse_parameter(pair)),
+            Rule::return_type => return_type = Some(parse_type(pair.into_inner().next().unwrap())),
+            Rule::block => body = Some(parse_block(pair, state)?),
+            _ => unreachable!(),
+        }
     }
+
+    Ok(FunctionDeclaration::new(
+        span,
+        identifier.unwrap(),
+        parameters,
+        return_type,
+        body.unwrap(),
+    ))
 }
 
-fn parse_while_loop(pair: Pair<Rule>) -> WhileLoop {
+fn parse_struct_declaration(pair: Pair<Rule>) -> StructDeclaration {
     let span = Span::new(pair.as_span().start(), pair.as_span().end());
     let mut inner_rules = pair.into_inner();
-    let condition = parse_expression(inner_rules.next().unwrap(), 0);
-    let mut body: Vec<Statement> = Vec::new();
+    let identifier = parse_identifier(inner_rules.next().unwrap());
+
+    let mut fields: Vec<StructField> = Vec::new();
+    for field_pair in inner_rules {
+        let field_span = Span::new(field_pair.as_span().start(), field_pair.as_span().end());
+        let mut field_rules = field_pair.into_inner();
+        let field_identifier = parse_identifier(field_rules.next().unwrap());
+        let data_type = parse_type(field_rules.next().unwrap());
+        fields.push(StructField::new(field_span, field_identifier, data_type));
+    }
+    StructDeclaration::new(span, identifier, fields)
+}
+
+fn parse_lambda(pair: Pair<Rule>, state: &mut ParserState) -> Result<Lambda, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let mut parameters: Vec<Parameter> = Vec::new();
+    let mut return_type: Option<DataType> = None;
+    let mut body: Option<Block> = None;
 
-    if let Some(basic_block_pair) = inner_rules.next() {
-        let mut inner_rules = basic_block_pair.into_inner();
-        while let Some(statement_pair) = inner_rules.next() {
-            body.push(parse_statement(statement_pair));
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::parameter => parameters.push(parse_parameter(pair)),
+            Rule::return_type => return_type = Some(parse_type(pair.into_inner().next().unwrap())),
+            Rule::block => body = Some(parse_block(pair, state)?),
+            _ => unreachable!(),
         }
     }
-    WhileLoop::new(span, condition, BasicBlock::new(body))
+
+    Ok(Lambda::new(span, parameters, return_type, body.unwrap()))
 }
 
-fn parse_basic_block(pair: Pair<Rule>) -> BasicBlock {
+fn parse_parameter(pair: Pair<Rule>) -> Parameter {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
     let mut inner_rules = pair.into_inner();
+    let identifier = parse_identifier(inner_rules.next().unwrap());
+    let data_type = parse_type(inner_rules.next().unwrap());
+    Parameter::new(span, identifier, data_type)
+}
+
+fn parse_type(pair: Pair<Rule>) -> DataType {
+    let type_pair = pair.into_inner().next().unwrap();
+    match type_pair.as_rule() {
+        Rule::primitive_type => match type_pair.as_str() {
+            "int" => DataType::Int,
+            "float" => DataType::Float,
+            "bool" => DataType::Bool,
+            "string" => DataType::Str,
+            "char" => DataType::Char,
+            _ => unreachable!(),
+        },
+        Rule::list_type => {
+            DataType::List(Box::new(parse_type(type_pair.into_inner().next().unwrap())))
+        }
+        Rule::map_type => {
+            let mut inner_rules = type_pair.into_inner();
+            let key_type = parse_type(inner_rules.next().unwrap());
+            let value_type = parse_type(inner_rules.next().unwrap());
+            DataType::Map(Box::new(key_type), Box::new(value_type))
+        }
+        Rule::identifier => DataType::Struct(type_pair.as_str().to_owned()),
+        Rule::function_type => {
+            let mut parameters: Vec<DataType> = Vec::new();
+            let mut return_type: Option<Box<DataType>> = None;
+            for pair in type_pair.into_inner() {
+                match pair.as_rule() {
+                    Rule::type_name => parameters.push(parse_type(pair)),
+                    Rule::return_type => {
+                        return_type = Some(Box::new(parse_type(pair.into_inner().next().unwrap())))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            DataType::Function(parameters, return_type)
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[inline]
+fn parse_identifier(pair: Pair<Rule>) -> Identifier {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    Identifier::new(pair.as_str().to_owned(), span)
+}
+
+fn parse_while_loop(pair: Pair<Rule>, state: &mut ParserState) -> Result<WhileLoop, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let mut inner_rules = pair.into_inner();
+    let condition = parse_expression(inner_rules.next().unwrap(), 0, state)?;
+
+    let body = parse_block(inner_rules.next().unwrap(), state)?;
+
+    Ok(WhileLoop::new(span, condition, body))
+}
+
+fn parse_if_statement(pair: Pair<Rule>, state: &mut ParserState) -> Result<IfStatement, Error> {
+    let mut branches: Vec<ConditionalBranch> = Vec::new();
+    let mut else_body: Option<Block> = None;
+
+    for pair in pair.into_inner() {
+        match pair.as_rule() {
+            Rule::conditional_branch => branches.push(parse_conditional_branch(pair, state)?),
+            Rule::else_branch => {
+                else_body = Some(parse_block(pair.into_inner().next().unwrap(), state)?)
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(IfStatement::new(branches, else_body))
+}
+
+fn parse_conditional_branch(
+    pair: Pair<Rule>,
+    state: &mut ParserState,
+) -> Result<ConditionalBranch, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let mut inner_rules = pair.into_inner();
+    let condition = parse_expression(inner_rules.next().unwrap(), 0, state)?;
+    let body = parse_block(inner_rules.next().unwrap(), state)?;
+    Ok(ConditionalBranch::new(span, condition, body))
+}
+
+fn parse_block(pair: Pair<Rule>, state: &mut ParserState) -> Result<Block, Error> {
+    let mut result: Vec<ControlFlow> = Vec::new();
+    for control_flow_pair in pair.into_inner() {
+        result.push(parse_control_flow(control_flow_pair, state)?);
+    }
+    Ok(Block::new(result))
+}
+
+fn parse_basic_block(pair: Pair<Rule>, state: &mut ParserState) -> Result<BasicBlock, Error> {
     let mut result: Vec<Statement> = Vec::new();
 
-    while let Some(pair) = inner_rules.next() {
+    for pair in pair.into_inner() {
         match pair.as_rule() {
             Rule::statement => {
-                result.push(parse_statement(pair));
+                result.push(parse_statement(pair, state)?);
             }
             Rule::EOI => (),
             _ => unreachable!(),
         }
     }
-    BasicBlock::new(result)
+    Ok(BasicBlock::new(result))
 }
 
-fn parse_statement(pair: Pair<Rule>) -> Statement {
-    match pair.as_rule() {
-        Rule::statement => parse_statement(pair.into_inner().next().unwrap()),
-        Rule::let_statement => Statement::LetStatement(parse_let_statement(pair)),
-        Rule::assignment => Statement::Assignment(parse_assignment(pair)),
-        Rule::return_statement => Statement::ReturnStatement(parse_return_statement(pair)),
-        Rule::expression_statement => parse_statement(pair.into_inner().next().unwrap()),
-        Rule::expression => Statement::Expression(parse_expression(pair, 0)),
+fn parse_statement(pair: Pair<Rule>, state: &mut ParserState) -> Result<Statement, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let statement = match pair.as_rule() {
+        Rule::statement => parse_statement(pair.into_inner().next().unwrap(), state)?,
+        Rule::let_statement => Statement::LetStatement(parse_let_statement(pair, state)?),
+        Rule::assignment => Statement::Assignment(parse_assignment(pair, state)?),
+        Rule::return_statement => Statement::ReturnStatement(parse_return_statement(pair, state)?),
+        Rule::break_statement => Statement::Break(span),
+        Rule::continue_statement => Statement::Continue(span),
+        Rule::expression_statement => parse_statement(pair.into_inner().next().unwrap(), state)?,
+        Rule::expression => Statement::Expression(parse_expression(pair, 0, state)?),
+        Rule::legacy_comment => {
+            let span = Span::new(pair.as_span().start(), pair.as_span().end());
+            return Err(state.new_error(ErrorKind::LegacyComment, span, span));
+        }
         _ => unreachable!(),
-    }
+    };
+    Ok(statement)
 }
 
-fn parse_let_statement(pair: Pair<Rule>) -> LetStatement {
+fn parse_let_statement(pair: Pair<Rule>, state: &mut ParserState) -> Result<LetStatement, Error> {
     let span = Span::new(pair.as_span().start(), pair.as_span().end());
     let mut inner_rules = pair.into_inner();
 
@@ -92,18 +277,34 @@ fn parse_let_statement(pair: Pair<Rule>) -> LetStatement {
     );
     let identifier = Identifier::new(identifier_pair.as_str().to_owned(), identifier_span);
 
-    let operator_pair = inner_rules.next().unwrap();
-    let operator_span = Span::new(
-        operator_pair.as_span().start(),
-        operator_pair.as_span().end(),
-    );
-
-    let expression = parse_expression(inner_rules.next().unwrap(), 0);
+    let mut type_annotation: Option<TypeAnnotation> = None;
+    let mut operator_span: Option<Span> = None;
+    let mut expression: Option<Box<Expression>> = None;
+    for pair in inner_rules {
+        match pair.as_rule() {
+            Rule::type_annotation => {
+                let annotation_span = Span::new(pair.as_span().start(), pair.as_span().end());
+                let data_type = parse_type(pair.into_inner().next().unwrap());
+                type_annotation = Some(TypeAnnotation::new(annotation_span, data_type));
+            }
+            Rule::assign => {
+                operator_span = Some(Span::new(pair.as_span().start(), pair.as_span().end()))
+            }
+            Rule::expression => expression = Some(Box::new(parse_expression(pair, 0, state)?)),
+            _ => unreachable!(),
+        }
+    }
 
-    LetStatement::new(span, identifier, operator_span, Box::new(expression))
+    Ok(LetStatement::new(
+        span,
+        identifier,
+        type_annotation,
+        operator_span,
+        expression,
+    ))
 }
 
-fn parse_assignment(pair: Pair<Rule>) -> Assignment {
+fn parse_assignment(pair: Pair<Rule>, state: &mut ParserState) -> Result<Assignment, Error> {
     let span = Span::new(pair.as_span().start(), pair.as_span().end());
     let mut inner_rules = pair.into_inner();
 
@@ -111,35 +312,68 @@ fn parse_assignment(pair: Pair<Rule>) -> Assignment {
     let lhs_span = Span::new(lhs_pair.as_span().start(), lhs_pair.as_span().end());
     let lhs = Identifier::new(lhs_pair.as_str().to_owned(), lhs_span);
 
-    let operator_pair = inner_rules.next().unwrap();
+    let mut accessors: Vec<Accessor> = Vec::new();
+    let mut operator_pair = inner_rules.next().unwrap();
+    loop {
+        match operator_pair.as_rule() {
+            Rule::index => {
+                let index_pair = operator_pair.into_inner().next().unwrap();
+                accessors.push(Accessor::Index(parse_expression(index_pair, 0, state)?));
+            }
+            Rule::field_access => {
+                // The first inner pair is the dot itself
+                let field_pair = operator_pair.into_inner().nth(1).unwrap();
+                accessors.push(Accessor::Field(parse_identifier(field_pair)));
+            }
+            _ => break,
+        }
+        operator_pair = inner_rules.next().unwrap();
+    }
     let operator_span = Span::new(
         operator_pair.as_span().start(),
         operator_pair.as_span().end(),
     );
     let operator = parse_assignment_operator(operator_pair);
 
-    let rhs = parse_expression(inner_rules.next().unwrap(), 0);
+    let rhs = parse_expression(inner_rules.next().unwrap(), 0, state)?;
 
-    Assignment::new(span, lhs, operator, operator_span, Box::new(rhs))
+    Ok(Assignment::new(
+        span,
+        lhs,
+        accessors,
+        operator,
+        operator_span,
+        Box::new(rhs),
+    ))
 }
 
-fn parse_return_statement(pair: Pair<Rule>) -> ReturnStatement {
+fn parse_return_statement(
+    pair: Pair<Rule>,
+    state: &mut ParserState,
+) -> Result<ReturnStatement, Error> {
     let span = Span::new(pair.as_span().start(), pair.as_span().end());
-    let expression = parse_expression(pair.into_inner().next().unwrap(), 0);
-    ReturnStatement::new(span, Box::new(expression))
+    let expression = match pair.into_inner().next() {
+        Some(pair) => Some(Box::new(parse_expression(pair, 0, state)?)),
+        None => None,
+    };
+    Ok(ReturnStatement::new(span, expression))
 }
 
-fn parse_expression(pair: Pair<Rule>, level: u8) -> Expression {
+fn parse_expression(
+    pair: Pair<Rule>,
+    level: u8,
+    state: &mut ParserState,
+) -> Result<Expression, Error> {
     let mut inner_rules = pair.into_inner();
 
     if level < MAX_BINARY_PRECEDENCE_DEPTH {
-        let mut left = parse_expression(inner_rules.next().unwrap(), level + 1);
+        let mut left = parse_expression(inner_rules.next().unwrap(), level + 1, state)?;
 
         while let Some(pair) = inner_rules.next() {
             let operator_span = Span::new(pair.as_span().start(), pair.as_span().end());
             let operator = parse_binary_operator(pair);
 
-            let right = parse_expression(inner_rules.next().unwrap(), level + 1);
+            let right = parse_expression(inner_rules.next().unwrap(), level + 1, state)?;
 
             left = Expression::BinaryOperation(BinaryOperation::new(
                 Span::new(left.span().start(), right.span().end()),
@@ -149,67 +383,304 @@ fn parse_expression(pair: Pair<Rule>, level: u8) -> Expression {
                 Box::new(right),
             ));
         }
-        left
-    } else if level < MAX_PRECEDENCE_DEPTH {
+        Ok(left)
+    } else if level == CAST_PRECEDENCE_DEPTH {
+        let mut result = parse_expression(inner_rules.next().unwrap(), level + 1, state)?;
+
+        // Casts are applied left to right, e.g. '1.5 as int as string'
+        for cast_pair in inner_rules {
+            let span = Span::new(result.span().start(), cast_pair.as_span().end());
+            // The first inner pair is the 'as' keyword itself
+            let type_pair = cast_pair.into_inner().nth(1).unwrap();
+            let type_span = Span::new(type_pair.as_span().start(), type_pair.as_span().end());
+            let data_type = parse_type(type_pair);
+            result = Expression::Cast(Cast::new(span, Box::new(result), type_span, data_type));
+        }
+        Ok(result)
+    } else if level == UNARY_PRECEDENCE_DEPTH {
         let pair = inner_rules.next().unwrap();
 
         // if there is no unary prefix operator at all, pass through
-        if let Rule::level_7 = pair.as_rule() {
-            return parse_expression(pair, level + 1);
+        if let Rule::level_12 = pair.as_rule() {
+            return parse_expression(pair, level + 1, state);
         }
 
         let operator_span = Span::new(pair.as_span().start(), pair.as_span().end());
         let operator = parse_unary_operator(pair);
+        let operand_pair = inner_rules.next().unwrap();
+
+        // A negated number is a single literal, so that e.g. the minimum int is in range
+        if matches!(operator, UnaryOperator::Neg)
+            && let Some(number_pair) = bare_number(&operand_pair)
+        {
+            let span = Span::new(operator_span.start(), number_pair.as_span().end());
+            let value = parse_number(number_pair, true, span, state)?;
+            return Ok(Expression::Literal(Literal::new(value, span)));
+        }
 
-        let operand = parse_expression(inner_rules.next().unwrap(), level);
+        let operand = parse_expression(operand_pair, level, state)?;
 
-        Expression::UnaryOperation(UnaryOperation::new(
+        Ok(Expression::UnaryOperation(UnaryOperation::new(
             Span::new(operator_span.start(), operand.span().end()),
             operator_span,
             operator,
             Box::new(operand),
-        ))
+        )))
+    } else if level == POWER_PRECEDENCE_DEPTH {
+        let base = parse_expression(inner_rules.next().unwrap(), level + 1, state)?;
+
+        let Some(pair) = inner_rules.next() else {
+            return Ok(base);
+        };
+        let operator_span = Span::new(pair.as_span().start(), pair.as_span().end());
+        let operator = parse_binary_operator(pair);
+
+        // The exponent is a unary expression, which makes '**' right-associative
+        let exponent =
+            parse_expression(inner_rules.next().unwrap(), UNARY_PRECEDENCE_DEPTH, state)?;
+
+        Ok(Expression::BinaryOperation(BinaryOperation::new(
+            Span::new(base.span().start(), exponent.span().end()),
+            operator_span,
+            Box::new(base),
+            operator,
+            Box::new(exponent),
+        )))
     } else {
         let pair = inner_rules.next().unwrap();
         let span_start = pair.as_span().start();
         let span_end = pair.as_span().end();
-        match pair.as_rule() {
-            Rule::number => Expression::Literal(Literal::new(
-                parse_number(pair),
-                Span::new(span_start, span_end),
-            )),
+        let mut result = match pair.as_rule() {
+            Rule::number => {
+                let span = Span::new(span_start, span_end);
+                Expression::Literal(Literal::new(parse_number(pair, false, span, state)?, span))
+            }
             Rule::boolean => Expression::Literal(Literal::new(
                 Value::Bool(pair.as_str().parse::<bool>().unwrap()),
                 Span::new(span_start, span_end),
             )),
-            Rule::string => Expression::Literal(Literal::new(
-                Value::Str(Box::new(pair.as_str().trim_matches('"').to_owned())),
-                Span::new(span_start, span_end),
-            )),
-            Rule::char => Expression::Literal(Literal::new(
-                Value::Char(pair.as_str().trim_matches('\'').parse::<char>().unwrap()),
-                Span::new(span_start, span_end),
-            )),
+            Rule::string => parse_string(pair, state)?,
+            Rule::char => {
+                let span = Span::new(span_start, span_end);
+                // Strip the quotes, which are a single byte each
+                let content = &pair.as_str()[1..pair.as_str().len() - 1];
+                let decoded = decode_escapes(content, span_start + 1, span, state)?;
+                // The grammar only allows a single character or escape sequence
+                let value = Value::Char(decoded.chars().next().unwrap());
+                Expression::Literal(Literal::new(value, span))
+            }
             Rule::identifier => Expression::Identifier(Identifier::new(
                 pair.as_str().to_owned(),
                 Span::new(span_start, span_end),
             )),
-            Rule::expression => parse_expression(pair, 0),
-            _ => {
-                dbg!(pair);
-                unreachable!()
+            Rule::lambda => Expression::Lambda(parse_lambda(pair, state)?),
+            Rule::list => {
+                let mut elements: Vec<Expression> = Vec::new();
+                for pair in pair.into_inner() {
+                    elements.push(parse_expression(pair, 0, state)?);
+                }
+                Expression::List(List::new(Span::new(span_start, span_end), elements))
+            }
+            Rule::map => {
+                let mut entries: Vec<(Expression, Expression)> = Vec::new();
+                for entry_pair in pair.into_inner() {
+                    let mut entry_rules = entry_pair.into_inner();
+                    let key = parse_expression(entry_rules.next().unwrap(), 0, state)?;
+                    let value = parse_expression(entry_rules.next().unwrap(), 0, state)?;
+                    entries.push((key, value));
+                }
+                Expression::Map(Map::new(Span::new(span_start, span_end), entries))
             }
+            Rule::struct_literal => {
+                let mut inner_rules = pair.into_inner();
+                let identifier = parse_identifier(inner_rules.next().unwrap());
+                let mut fields: Vec<(Identifier, Expression)> = Vec::new();
+                for initializer_pair in inner_rules {
+                    let mut initializer_rules = initializer_pair.into_inner();
+                    let field = parse_identifier(initializer_rules.next().unwrap());
+                    let value = parse_expression(initializer_rules.next().unwrap(), 0, state)?;
+                    fields.push((field, value));
+                }
+                Expression::StructLiteral(StructLiteral::new(
+                    Span::new(span_start, span_end),
+                    identifier,
+                    fields,
+                ))
+            }
+            Rule::expression => parse_expression(pair, 0, state)?,
+            _ => unreachable!(),
+        };
+
+        // Every remaining pair is a postfix call, index or field access on the result so far,
+        // e.g. 'f(1)[2].x'
+        for postfix_pair in inner_rules {
+            let span = Span::new(result.span().start(), postfix_pair.as_span().end());
+            result = match postfix_pair.as_rule() {
+                Rule::call_arguments => {
+                    let mut arguments: Vec<Expression> = Vec::new();
+                    for pair in postfix_pair.into_inner() {
+                        arguments.push(parse_expression(pair, 0, state)?);
+                    }
+                    Expression::Call(Call::new(span, Box::new(result), arguments))
+                }
+                Rule::index => {
+                    let index =
+                        parse_expression(postfix_pair.into_inner().next().unwrap(), 0, state)?;
+                    Expression::Index(Index::new(span, Box::new(result), Box::new(index)))
+                }
+                Rule::field_access => {
+                    let field = parse_identifier(postfix_pair.into_inner().nth(1).unwrap());
+                    Expression::FieldAccess(FieldAccess::new(span, Box::new(result), field))
+                }
+                _ => unreachable!(),
+            };
+        }
+        Ok(result)
+    }
+}
+
+// A string without embedded expressions is a plain literal
+fn parse_string(pair: Pair<Rule>, state: &mut ParserState) -> Result<Expression, Error> {
+    let span = Span::new(pair.as_span().start(), pair.as_span().end());
+    let mut parts: Vec<InterpolationPart> = Vec::new();
+    for part_pair in pair.into_inner() {
+        match part_pair.as_rule() {
+            Rule::text => {
+                let start = part_pair.as_span().start();
+                let text = decode_escapes(part_pair.as_str(), start, span, state)?;
+                parts.push(InterpolationPart::Text(text));
+            }
+            Rule::interpolation => {
+                let expression_pair = part_pair.into_inner().next().unwrap();
+                parts.push(InterpolationPart::Expression(parse_expression(
+                    expression_pair,
+                    0,
+                    state,
+                )?));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    match parts.as_slice() {
+        [] => Ok(Expression::Literal(Literal::new(
+            Value::Str(Box::default()),
+            span,
+        ))),
+        [InterpolationPart::Text(text)] => Ok(Expression::Literal(Literal::new(
+            Value::Str(Box::new(text.clone())),
+            span,
+        ))),
+        _ => Ok(Expression::Interpolation(Interpolation::new(span, parts))),
+    }
+}
+
+// Replaces the escape sequences and doubled braces in the content of a string or char literal.
+// The content starts at the given position in the source code, which is used to report invalid escapes.
+fn decode_escapes(
+    content: &str,
+    start: usize,
+    context: Span,
+    state: &ParserState,
+) -> Result<String, Error> {
+    let mut result = String::with_capacity(content.len());
+    let mut chars = content.char_indices().peekable();
+    while let Some((escape_start, ch)) = chars.next() {
+        if ch == '{' || ch == '}' {
+            // Literal braces are written twice in strings, since a single one starts an interpolation
+            chars.next_if(|(_, next)| *next == ch);
+            result.push(ch);
+            continue;
         }
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        let decoded = match chars.next().map(|(_, escaped)| escaped) {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('0') => Some('\0'),
+            Some('\\') => Some('\\'),
+            Some('"') => Some('"'),
+            Some('\'') => Some('\''),
+            Some('u') => decode_unicode_escape(&mut chars),
+            _ => None,
+        };
+        match decoded {
+            Some(decoded) => result.push(decoded),
+            None => {
+                let escape_end = chars.peek().map_or(content.len(), |(idx, _)| *idx);
+                return Err(state.new_error(
+                    ErrorKind::InvalidEscape(content[escape_start..escape_end].to_owned()),
+                    context,
+                    Span::new(start + escape_start, start + escape_end),
+                ));
+            }
+        }
+    }
+    Ok(result)
+}
+
+// Decodes the '{1F600}' part of a unicode escape, which has one to six hex digits
+fn decode_unicode_escape(chars: &mut std::iter::Peekable<std::str::CharIndices>) -> Option<char> {
+    chars.next_if(|(_, ch)| *ch == '{')?;
+    let mut digits = String::new();
+    loop {
+        match chars.next()? {
+            (_, '}') => break,
+            (_, ch) => digits.push(ch),
+        }
+    }
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        return None;
     }
+    char::from_u32(u32::from_str_radix(&digits, 16).unwrap())
+}
+
+// Returns the number if the unary operand is nothing but a number token, which excludes e.g. '-(1)'
+fn bare_number<'a>(pair: &Pair<'a, Rule>) -> Option<Pair<'a, Rule>> {
+    let mut pair = pair.clone();
+    for rule in [Rule::level_11, Rule::level_12, Rule::level_13] {
+        if pair.as_rule() != rule {
+            return None;
+        }
+        let mut inner_rules = pair.into_inner();
+        pair = inner_rules.next()?;
+        if inner_rules.next().is_some() {
+            return None;
+        }
+    }
+    (pair.as_rule() == Rule::number).then_some(pair)
 }
 
 #[inline]
-fn parse_number(pair: Pair<Rule>) -> Value {
-    let number_string = pair.as_str().replace('_', "");
-    if let Ok(result) = number_string.parse::<i64>() {
-        Value::Int(result)
-    } else {
-        Value::Float(number_string.parse::<f64>().unwrap())
+fn parse_number(
+    pair: Pair<Rule>,
+    negative: bool,
+    span: Span,
+    state: &ParserState,
+) -> Result<Value, Error> {
+    let digits = pair.as_str().replace('_', "");
+    let sign = if negative { "-" } else { "" };
+
+    let radix = match digits.get(..2) {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        Some("0o") => 8,
+        _ => 10,
+    };
+    if radix == 10 && digits.contains(['.', 'e', 'E']) {
+        return Ok(Value::Float(
+            format!("{sign}{digits}").parse::<f64>().unwrap(),
+        ));
+    }
+
+    let digits = if radix == 10 { &digits } else { &digits[2..] };
+    // The sign is parsed along with the digits, so that the minimum value is in range
+    match i64::from_str_radix(&format!("{sign}{digits}"), radix) {
+        Ok(result) => Ok(Value::Int(result)),
+        Err(_) => Err(state.new_error(ErrorKind::IntegerOutOfRange, span, span)),
     }
 }
 
@@ -224,9 +695,16 @@ fn parse_binary_operator(pair: Pair<Rule>) -> BinaryOperator {
         Rule::less_eq => BinaryOperator::LessEq,
         Rule::greater_than => BinaryOperator::GreaterThan,
         Rule::greater_eq => BinaryOperator::GreaterEq,
+        Rule::bit_or => BinaryOperator::BitOr,
+        Rule::bit_xor => BinaryOperator::BitXor,
+        Rule::bit_and => BinaryOperator::BitAnd,
+        Rule::shift_left => BinaryOperator::ShiftLeft,
+        Rule::shift_right => BinaryOperator::ShiftRight,
         Rule::add => BinaryOperator::Add,
         Rule::sub => BinaryOperator::Sub,
+        Rule::pow => BinaryOperator::Pow,
         Rule::mul => BinaryOperator::Mul,
+        Rule::floor_div => BinaryOperator::FloorDiv,
         Rule::div => BinaryOperator::Div,
         Rule::modulo => BinaryOperator::Mod,
         _ => unreachable!(),
@@ -238,6 +716,7 @@ fn parse_unary_operator(pair: Pair<Rule>) -> UnaryOperator {
     match pair.as_rule() {
         Rule::not => UnaryOperator::Not,
         Rule::neg => UnaryOperator::Neg,
+        Rule::bit_not => UnaryOperator::BitNot,
         _ => unreachable!(),
     }
 }
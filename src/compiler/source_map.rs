@@ -0,0 +1,117 @@
+use super::error::{Error, ErrorKind};
+use super::language_components::Span;
+use crate::interpreter::RuntimeError;
+
+// Source location of every instruction, used to point runtime errors at the code which caused them
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    filename: String,
+    source_code: String,
+    // Span of the innermost expression or statement each instruction was compiled from
+    spans: Vec<Span>,
+    variables: Vec<Variable>,
+    // Instruction ranges of function bodies, each call of which gets its own register window
+    function_bodies: Vec<(usize, usize)>,
+}
+
+impl SourceMap {
+    pub fn new(
+        filename: String,
+        source_code: String,
+        spans: Vec<Span>,
+        variables: Vec<Variable>,
+        function_bodies: Vec<(usize, usize)>,
+    ) -> Self {
+        SourceMap {
+            filename,
+            source_code,
+            spans,
+            variables,
+            function_bodies,
+        }
+    }
+
+    // Line number of the instruction, counting from 1
+    pub fn line(&self, program_counter: usize) -> Option<usize> {
+        let span = self.spans.get(program_counter)?;
+        Some(self.source_code[..span.start()].matches('\n').count() + 1)
+    }
+
+    pub fn line_text(&self, line: usize) -> Option<&str> {
+        self.source_code.lines().nth(line.checked_sub(1)?)
+    }
+
+    // Variables in scope at the instruction, which are stored in the current register window
+    pub fn variables_at(&self, program_counter: usize) -> impl Iterator<Item = &Variable> {
+        let depth = self
+            .function_bodies
+            .iter()
+            .filter(|(start, end)| (*start..*end).contains(&program_counter))
+            .count();
+        self.variables.iter().filter(move |variable| {
+            variable.depth == depth && (variable.start..variable.end).contains(&program_counter)
+        })
+    }
+
+    // Renders the error like a compile error, or just names the instruction if its location is unknown
+    pub fn format_error(&self, error: &RuntimeError) -> String {
+        match self.spans.get(error.program_counter()) {
+            Some(span) => Error::new(
+                self.filename.clone(),
+                self.source_code.clone(),
+                ErrorKind::Runtime(error.kind().clone()),
+                *span,
+                *span,
+            )
+            .format_to_string(),
+            None => format!(
+                "Runtime error at instruction {}: {}",
+                error.program_counter(),
+                error.kind()
+            ),
+        }
+    }
+}
+
+// Register of a variable from the point it is declared until its scope ends
+#[derive(Clone, Debug)]
+pub struct Variable {
+    name: String,
+    register: u8,
+    // Number of function bodies the declaration is nested in
+    depth: usize,
+    start: usize,
+    end: usize,
+}
+
+impl Variable {
+    pub fn new(name: String, register: u8, depth: usize, start: usize) -> Self {
+        Variable {
+            name,
+            register,
+            depth,
+            start,
+            end: usize::MAX,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn register(&self) -> u8 {
+        self.register
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.end == usize::MAX
+    }
+
+    pub fn close(&mut self, end: usize) {
+        self.end = end;
+    }
+}
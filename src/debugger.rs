@@ -0,0 +1,253 @@
+use crate::interpreter::{Status, Thread};
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+Commands:
+  break <line>      Stop before the first instruction of a source line
+  break @<index>    Stop before the instruction with the given index
+  delete <line>     Remove a breakpoint, use @<index> for instruction breakpoints
+  step [count]      Execute one or the given number of instructions
+  continue          Run until the next breakpoint or the end of the program
+  registers         Show the variables in scope and the registers holding them
+  where             Show the instruction which is executed next
+  quit              Stop debugging
+Commands can be abbreviated to their first letter.";
+
+// Runs a thread under the control of commands, which are read line by line
+pub struct Debugger {
+    thread: Thread,
+    line_breakpoints: BTreeSet<usize>,
+    instruction_breakpoints: BTreeSet<usize>,
+
+    output: Box<dyn Write>,
+    // Reads from stdin if not set, which is only locked for the duration of a single read
+    // so that the program can read from stdin as well
+    input: Option<Box<dyn BufRead>>,
+}
+
+impl Debugger {
+    pub fn new(thread: Thread) -> Self {
+        Debugger {
+            thread,
+            line_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            output: Box::new(std::io::stdout()),
+            input: None,
+        }
+    }
+
+    // Redirects the output of the debugger, which goes to stdout by default
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Redirects the commands, which come from stdin by default
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
+    }
+
+    // Pauses before the first instruction and then executes commands until the program ends,
+    // the user quits or the input is exhausted
+    pub fn run(&mut self) -> std::io::Result<()> {
+        writeln!(self.output, "Paused at the start of the program")?;
+        self.print_location()?;
+        loop {
+            write!(self.output, "(debug) ")?;
+            self.output.flush()?;
+            let mut line = String::new();
+            let bytes_read = match &mut self.input {
+                Some(input) => input.read_line(&mut line)?,
+                None => std::io::stdin().read_line(&mut line)?,
+            };
+            if bytes_read == 0 {
+                writeln!(self.output)?;
+                return Ok(());
+            }
+
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            let argument = words.next();
+            let status = match command {
+                "b" | "break" => {
+                    self.add_breakpoint(argument)?;
+                    continue;
+                }
+                "d" | "delete" => {
+                    self.remove_breakpoint(argument)?;
+                    continue;
+                }
+                "s" | "step" => {
+                    let count = match argument.map(str::parse::<u64>) {
+                        None => 1,
+                        Some(Ok(count)) if count > 0 => count,
+                        Some(_) => {
+                            writeln!(self.output, "Expected a positive number of steps")?;
+                            continue;
+                        }
+                    };
+                    self.thread.run_for(count)
+                }
+                "c" | "continue" => self.continue_to_breakpoint(),
+                "r" | "registers" => {
+                    self.print_variables()?;
+                    continue;
+                }
+                "w" | "where" => {
+                    self.print_location()?;
+                    continue;
+                }
+                "h" | "help" => {
+                    writeln!(self.output, "{HELP}")?;
+                    continue;
+                }
+                "q" | "quit" => return Ok(()),
+                _ => {
+                    writeln!(
+                        self.output,
+                        "Unknown command '{command}', enter 'help' for a list of commands"
+                    )?;
+                    continue;
+                }
+            };
+
+            match status {
+                Status::Running => self.print_location()?,
+                Status::Finished(value) => {
+                    writeln!(self.output, "Program finished")?;
+                    if let Some(value) = value {
+                        writeln!(self.output, "{value}")?;
+                    }
+                    return Ok(());
+                }
+                Status::Error(error) => {
+                    writeln!(
+                        self.output,
+                        "{}",
+                        self.thread.source_map().format_error(&error)
+                    )?;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Executes instructions until one with a breakpoint is reached. Line breakpoints only trigger
+    // when execution enters the line, not for every instruction on it.
+    fn continue_to_breakpoint(&mut self) -> Status {
+        let source_map = self.thread.source_map();
+        let mut previous_line = source_map.line(self.thread.program_counter());
+        loop {
+            let status = self.thread.step();
+            if status != Status::Running {
+                return status;
+            }
+            let program_counter = self.thread.program_counter();
+            let line = self.thread.source_map().line(program_counter);
+            let entered_line = line != previous_line
+                && line.is_some_and(|line| self.line_breakpoints.contains(&line));
+            if entered_line || self.instruction_breakpoints.contains(&program_counter) {
+                return Status::Running;
+            }
+            previous_line = line;
+        }
+    }
+
+    // Breakpoints are given as a source line or as an instruction index prefixed with '@'
+    fn parse_breakpoint(&mut self, argument: Option<&str>) -> std::io::Result<Option<Breakpoint>> {
+        let breakpoint = match argument {
+            Some(argument) => match argument.strip_prefix('@') {
+                Some(index) => index.parse().ok().map(Breakpoint::Instruction),
+                None => argument.parse().ok().map(Breakpoint::Line),
+            },
+            None => None,
+        };
+        if breakpoint.is_none() {
+            writeln!(
+                self.output,
+                "Expected a line number or an instruction index like @12"
+            )?;
+        }
+        Ok(breakpoint)
+    }
+
+    fn add_breakpoint(&mut self, argument: Option<&str>) -> std::io::Result<()> {
+        let instruction_count = self.thread.instructions().len();
+        match self.parse_breakpoint(argument)? {
+            Some(Breakpoint::Line(line)) => {
+                let source_map = self.thread.source_map();
+                if !(0..instruction_count).any(|idx| source_map.line(idx) == Some(line)) {
+                    writeln!(self.output, "There is no code on line {line}")?;
+                } else if self.line_breakpoints.insert(line) {
+                    writeln!(self.output, "Breakpoint set on line {line}")?;
+                }
+            }
+            Some(Breakpoint::Instruction(idx)) => {
+                if idx >= instruction_count {
+                    writeln!(
+                        self.output,
+                        "There is no instruction {idx}, the program has {instruction_count}"
+                    )?;
+                } else if self.instruction_breakpoints.insert(idx) {
+                    writeln!(self.output, "Breakpoint set on instruction {idx}")?;
+                }
+            }
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn remove_breakpoint(&mut self, argument: Option<&str>) -> std::io::Result<()> {
+        let removed = match self.parse_breakpoint(argument)? {
+            Some(Breakpoint::Line(line)) => self.line_breakpoints.remove(&line),
+            Some(Breakpoint::Instruction(idx)) => self.instruction_breakpoints.remove(&idx),
+            None => return Ok(()),
+        };
+        if !removed {
+            writeln!(self.output, "There is no such breakpoint")?;
+        }
+        Ok(())
+    }
+
+    fn print_location(&mut self) -> std::io::Result<()> {
+        let program_counter = self.thread.program_counter();
+        let source_map = self.thread.source_map();
+        let Some(instruction) = self.thread.instructions().get(program_counter) else {
+            return writeln!(self.output, "End of the program");
+        };
+        writeln!(self.output, "{program_counter:<4} {instruction}")?;
+        if let Some(line) = source_map.line(program_counter)
+            && let Some(text) = source_map.line_text(line)
+        {
+            writeln!(self.output, "{line:>4}| {}", text.trim())?;
+        }
+        Ok(())
+    }
+
+    fn print_variables(&mut self) -> std::io::Result<()> {
+        let program_counter = self.thread.program_counter();
+        let mut any_variables = false;
+        for variable in self.thread.source_map().variables_at(program_counter) {
+            let value = self.thread.register(variable.register());
+            writeln!(
+                self.output,
+                "r{:<3} {} = {value}",
+                variable.register(),
+                variable.name()
+            )?;
+            any_variables = true;
+        }
+        if !any_variables {
+            writeln!(self.output, "No variables in scope")?;
+        }
+        Ok(())
+    }
+}
+
+enum Breakpoint {
+    Line(usize),
+    Instruction(usize),
+}
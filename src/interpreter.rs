@@ -1,314 +1,908 @@
+use crate::compiler::SourceMap;
 use crate::opcode::Opcode;
+use crate::program::Program;
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+// Every frame keeps a whole register window alive, so this bounds the memory used by recursion
+const MAX_CALL_DEPTH: usize = 10_000;
+
+struct CallFrame {
+    return_address: usize,
+    result_register: u8,
+    registers: Box<[Value; 256]>,
+}
+
+// Outcome of running a thread for some instructions
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    // There are instructions left to execute
+    Running,
+    // The program ended, with the value it returned if any
+    Finished(Option<Value>),
+    // Execution stopped, either for good or until more fuel is added
+    Error(RuntimeError),
+}
 
 pub struct Thread {
     instructions: Vec<Opcode>,
+    constants: Vec<Value>,
+    source_map: SourceMap,
     program_counter: usize,
 
+    // Register window of the function that is currently executing
     registers: Box<[Value; 256]>,
+    // Register windows of the callers, innermost last
+    call_stack: Vec<CallFrame>,
+    arguments: Vec<Value>,
     return_value: Option<Value>,
+    // Number of instructions which may still be executed, unlimited if not set
+    fuel: Option<u64>,
+
+    output: Box<dyn Write>,
+    // Reads from stdin if not set, which is only locked for the duration of a single read
+    input: Option<Box<dyn BufRead>>,
 }
 
 impl Thread {
-    pub fn new(instructions: Vec<Opcode>) -> Self {
-        let registers: [Value; 256] = [const { Value::Int(0) }; 256];
-
+    pub fn new(program: Program) -> Self {
+        let (instructions, constants, source_map) = program.into_parts();
         Thread {
             instructions,
+            constants,
+            source_map,
             program_counter: 0,
-            registers: Box::new(registers),
+            registers: Self::new_register_window(),
+            call_stack: Vec::new(),
+            arguments: Vec::new(),
             return_value: None,
+            fuel: None,
+            output: Box::new(std::io::stdout()),
+            input: None,
+        }
+    }
+
+    // Redirects the output of print and println, which goes to stdout by default
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // Redirects the input of read_line, which comes from stdin by default
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
+    }
+
+    // Limits how many more instructions may be executed, e.g. to stop scripts which never finish
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    fn read_line(input: &mut Option<Box<dyn BufRead>>) -> std::io::Result<String> {
+        let mut line = String::new();
+        match input {
+            Some(input) => input.read_line(&mut line)?,
+            None => std::io::stdin().read_line(&mut line)?,
+        };
+        if line.ends_with('\n') {
+            line.pop();
+            if line.ends_with('\r') {
+                line.pop();
+            }
+        }
+        Ok(line)
+    }
+
+    #[inline]
+    fn new_register_window() -> Box<[Value; 256]> {
+        Box::new([const { Value::Int(0) }; 256])
+    }
+
+    // Creates the register window of a callee with the queued arguments in its first registers
+    #[inline]
+    fn take_arguments(&mut self, arg_count: u8) -> Result<Box<[Value; 256]>, RuntimeErrorKind> {
+        let mut registers = Self::new_register_window();
+        let first_arg_idx = self
+            .arguments
+            .len()
+            .checked_sub(arg_count as usize)
+            .ok_or(RuntimeErrorKind::InvalidBytecode)?;
+        for (i, arg) in self.arguments.drain(first_arg_idx..).enumerate() {
+            registers[i] = arg;
+        }
+        Ok(registers)
+    }
+
+    #[inline]
+    fn enter_function(
+        &mut self,
+        mut registers: Box<[Value; 256]>,
+        address: u32,
+        res_idx: u8,
+    ) -> Result<(), RuntimeErrorKind> {
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err(RuntimeErrorKind::StackOverflow);
+        }
+        std::mem::swap(&mut self.registers, &mut registers);
+        self.call_stack.push(CallFrame {
+            return_address: self.program_counter,
+            result_register: res_idx,
+            registers,
+        });
+        self.program_counter = address as usize;
+        Ok(())
+    }
+
+    #[inline]
+    fn checked_index(index: i64, len: usize) -> Result<usize, RuntimeErrorKind> {
+        match usize::try_from(index) {
+            Ok(idx) if idx < len => Ok(idx),
+            _ => Err(RuntimeErrorKind::IndexOutOfBounds(index, len)),
         }
     }
 
-    pub fn return_value(&self) -> &Option<Value> {
-        &self.return_value
+    #[inline]
+    fn checked_floor_div(lhs: i64, rhs: i64) -> Result<i64, RuntimeErrorKind> {
+        if rhs == 0 {
+            return Err(RuntimeErrorKind::DivisionByZero);
+        }
+        let quotient = lhs
+            .checked_div(rhs)
+            .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+        // Integer division truncates, so step down when the signs differ and there is a remainder
+        if lhs % rhs != 0 && (lhs < 0) != (rhs < 0) {
+            Ok(quotient - 1)
+        } else {
+            Ok(quotient)
+        }
     }
 
-    pub fn exec(&mut self) {
+    // The remainder takes the sign of the divisor, so that (lhs // rhs) * rhs + lhs % rhs == lhs
+    #[inline]
+    fn checked_floor_mod(lhs: i64, rhs: i64) -> Result<i64, RuntimeErrorKind> {
+        if rhs == 0 {
+            return Err(RuntimeErrorKind::DivisionByZero);
+        }
+        let remainder = lhs
+            .checked_rem(rhs)
+            .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+        if remainder != 0 && (remainder < 0) != (rhs < 0) {
+            Ok(remainder + rhs)
+        } else {
+            Ok(remainder)
+        }
+    }
+
+    #[inline]
+    fn checked_pow(base: i64, exponent: i64) -> Result<i64, RuntimeErrorKind> {
+        if exponent < 0 {
+            return Err(RuntimeErrorKind::NegativeExponent(exponent));
+        }
+        match base {
+            // These never overflow, no matter how large the exponent is
+            0 | 1 if exponent > 0 => Ok(base),
+            -1 => Ok(if exponent % 2 == 0 { 1 } else { -1 }),
+            _ => u32::try_from(exponent)
+                .ok()
+                .and_then(|exponent| base.checked_pow(exponent))
+                .ok_or(RuntimeErrorKind::IntegerOverflow),
+        }
+    }
+
+    // Locations of the instructions, e.g. to render runtime errors
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    pub fn instructions(&self) -> &[Opcode] {
+        &self.instructions
+    }
+
+    // Index of the instruction which is executed next
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    // Value of a register in the window of the function that is currently executing
+    pub fn register(&self, idx: u8) -> &Value {
+        &self.registers[idx as usize]
+    }
+
+    // Runs the program from the start and returns the value it saved, if any
+    pub fn exec(&mut self) -> Result<Option<Value>, RuntimeError> {
         self.program_counter = 0;
-        while self.program_counter < self.instructions.len() {
-            match &self.instructions[self.program_counter] {
-                Opcode::Or(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_bool();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_bool();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs || rhs);
-                }
-                Opcode::And(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_bool();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_bool();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs && rhs);
-                }
+        self.call_stack.clear();
+        self.arguments.clear();
+        self.return_value = None;
+        self.resume()
+    }
 
-                Opcode::EqualInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
-                }
-                Opcode::EqualFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
-                }
-                Opcode::EqualBool(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_bool();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_bool();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
-                }
-                Opcode::EqualStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
-                }
-                Opcode::EqualChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
-                }
+    // Continues where the last execution stopped and runs until the program ends or fails.
+    // Meant for when it ran out of fuel, so call set_fuel first, otherwise it stops again right away.
+    pub fn resume(&mut self) -> Result<Option<Value>, RuntimeError> {
+        loop {
+            match self.step() {
+                Status::Running => (),
+                Status::Finished(value) => return Ok(value),
+                Status::Error(error) => return Err(error),
+            }
+        }
+    }
 
-                Opcode::NotEqualInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
-                }
-                Opcode::NotEqualFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
-                }
-                Opcode::NotEqualBool(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_bool();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_bool();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
-                }
-                Opcode::NotEqualStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
-                }
-                Opcode::NotEqualChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
-                }
+    // Executes at most the given number of instructions, so a host can switch between threads
+    pub fn run_for(&mut self, steps: u64) -> Status {
+        for _ in 0..steps {
+            let status = self.step();
+            if !matches!(status, Status::Running) {
+                return status;
+            }
+        }
+        self.status()
+    }
 
-                Opcode::LessThanInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
-                }
-                Opcode::LessThanFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
-                }
-                Opcode::LessThanStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
-                }
-                Opcode::LessThanChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
-                }
+    // Executes a single instruction
+    pub fn step(&mut self) -> Status {
+        if self.program_counter >= self.instructions.len() {
+            return self.status();
+        }
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                // The program counter still points at the instruction to continue with
+                let error = RuntimeError::new(RuntimeErrorKind::OutOfFuel, self.program_counter);
+                return Status::Error(error);
+            }
+            *fuel -= 1;
+        }
+        match self.execute_instruction() {
+            Ok(()) => self.status(),
+            Err(kind) => Status::Error(RuntimeError::new(kind, self.program_counter)),
+        }
+    }
 
-                Opcode::LessEqInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
-                }
-                Opcode::LessEqFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
-                }
-                Opcode::LessEqStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
-                }
-                Opcode::LessEqChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
-                }
+    #[inline]
+    fn status(&self) -> Status {
+        if self.program_counter < self.instructions.len() {
+            Status::Running
+        } else {
+            Status::Finished(self.return_value.clone())
+        }
+    }
 
-                Opcode::GreaterThanInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
-                }
-                Opcode::GreaterThanFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
-                }
-                Opcode::GreaterThanStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
-                }
-                Opcode::GreaterThanChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
-                }
+    fn execute_instruction(&mut self) -> Result<(), RuntimeErrorKind> {
+        match &self.instructions[self.program_counter] {
+            Opcode::Or(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_bool()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_bool()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs || rhs);
+            }
+            Opcode::And(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_bool()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_bool()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs && rhs);
+            }
 
-                Opcode::GreaterEqInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
-                }
-                Opcode::GreaterEqFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
-                }
-                Opcode::GreaterEqStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
-                }
-                Opcode::GreaterEqChar(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_char();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_char();
-                    self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
-                }
+            Opcode::EqualInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
+            }
+            Opcode::EqualFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
+            }
+            Opcode::EqualBool(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_bool()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_bool()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
+            }
+            Opcode::EqualStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
+            }
+            Opcode::EqualChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs == rhs);
+            }
 
-                Opcode::AddInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(lhs + rhs);
-                }
-                Opcode::AddFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(lhs + rhs);
-                }
-                Opcode::AddStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_string();
-                    self.registers[*res_idx as usize] =
-                        Value::Str(Box::new(String::from(lhs.as_str()) + rhs.as_str()));
-                }
+            Opcode::NotEqualInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
+            }
+            Opcode::NotEqualFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
+            }
+            Opcode::NotEqualBool(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_bool()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_bool()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
+            }
+            Opcode::NotEqualStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
+            }
+            Opcode::NotEqualChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs != rhs);
+            }
+
+            Opcode::LessThanInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
+            }
+            Opcode::LessThanFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
+            }
+            Opcode::LessThanStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
+            }
+            Opcode::LessThanChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs < rhs);
+            }
+
+            Opcode::LessEqInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
+            }
+            Opcode::LessEqFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
+            }
+            Opcode::LessEqStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
+            }
+            Opcode::LessEqChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs <= rhs);
+            }
+
+            Opcode::GreaterThanInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
+            }
+            Opcode::GreaterThanFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
+            }
+            Opcode::GreaterThanStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
+            }
+            Opcode::GreaterThanChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs > rhs);
+            }
+
+            Opcode::GreaterEqInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
+            }
+            Opcode::GreaterEqFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
+            }
+            Opcode::GreaterEqStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
+            }
+            Opcode::GreaterEqChar(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_char()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Bool(lhs >= rhs);
+            }
 
-                Opcode::SubInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(lhs - rhs);
+            Opcode::AddInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                let result = lhs
+                    .checked_add(rhs)
+                    .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+            Opcode::AddFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(lhs + rhs);
+            }
+            Opcode::AddStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_string()?;
+                self.registers[*res_idx as usize] =
+                    Value::Str(Rc::new(String::from(lhs.as_str()) + rhs.as_str()));
+            }
+
+            Opcode::SubInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                let result = lhs
+                    .checked_sub(rhs)
+                    .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+            Opcode::SubFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(lhs - rhs);
+            }
+
+            Opcode::MulInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                let result = lhs
+                    .checked_mul(rhs)
+                    .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+            Opcode::MulFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(lhs * rhs);
+            }
+            Opcode::MulStr(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_string()?;
+                let mut rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                if rhs < 0 {
+                    rhs = 0;
                 }
-                Opcode::SubFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(lhs - rhs);
+                // Strings can't be longer than isize::MAX bytes, repeat panics beyond that
+                match lhs.len().checked_mul(rhs as usize) {
+                    Some(len) if len <= isize::MAX as usize => (),
+                    _ => return Err(RuntimeErrorKind::IntegerOverflow),
                 }
+                self.registers[*res_idx as usize] = Value::Str(Rc::new(lhs.repeat(rhs as usize)));
+            }
 
-                Opcode::MulInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(lhs * rhs);
+            Opcode::DivInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                if rhs == 0 {
+                    return Err(RuntimeErrorKind::DivisionByZero);
                 }
-                Opcode::MulFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(lhs * rhs);
+                match lhs.checked_div(rhs) {
+                    Some(result) => self.registers[*res_idx as usize] = Value::Int(result),
+                    None => {
+                        return Err(RuntimeErrorKind::IntegerOverflow);
+                    }
                 }
-                Opcode::MulStr(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_string();
-                    let mut rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    if rhs < 0 {
-                        rhs = 0;
+            }
+            Opcode::DivFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(lhs / rhs);
+            }
+
+            Opcode::FloorDivInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                match Self::checked_floor_div(lhs, rhs) {
+                    Ok(result) => self.registers[*res_idx as usize] = Value::Int(result),
+                    Err(error) => {
+                        return Err(error);
                     }
-                    self.registers[*res_idx as usize] =
-                        Value::Str(Box::new(lhs.repeat(rhs as usize)));
                 }
+            }
+            Opcode::FloorDivFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float((lhs / rhs).floor());
+            }
 
-                Opcode::DivInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(lhs / rhs);
+            Opcode::ModInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                match Self::checked_floor_mod(lhs, rhs) {
+                    Ok(result) => self.registers[*res_idx as usize] = Value::Int(result),
+                    Err(error) => {
+                        return Err(error);
+                    }
                 }
-                Opcode::DivFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(lhs / rhs);
+            }
+            Opcode::ModFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                let remainder = lhs % rhs;
+                // Like the int version, a non-zero remainder takes the sign of the divisor
+                let result = if remainder != 0.0 && (remainder < 0.0) != (rhs < 0.0) {
+                    remainder + rhs
+                } else {
+                    remainder
+                };
+                self.registers[*res_idx as usize] = Value::Float(result);
+            }
+
+            Opcode::PowInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                match Self::checked_pow(lhs, rhs) {
+                    Ok(result) => self.registers[*res_idx as usize] = Value::Int(result),
+                    Err(error) => {
+                        return Err(error);
+                    }
                 }
+            }
+            Opcode::PowFloat(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_float()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(lhs.powf(rhs));
+            }
+
+            Opcode::BitAndInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Int(lhs & rhs);
+            }
+            Opcode::BitOrInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Int(lhs | rhs);
+            }
+            Opcode::BitXorInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Int(lhs ^ rhs);
+            }
+            Opcode::ShiftLeftInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                let result = match u32::try_from(rhs) {
+                    Ok(amount) if amount < i64::BITS => lhs << amount,
+                    _ => 0,
+                };
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+            Opcode::ShiftRightInt(lhs_idx, rhs_idx, res_idx) => {
+                let lhs = self.registers[*lhs_idx as usize].unwrap_int()?;
+                let rhs = self.registers[*rhs_idx as usize].unwrap_int()?;
+                let result = match u32::try_from(rhs) {
+                    Ok(amount) if amount < i64::BITS => lhs >> amount,
+                    // Only the sign bit is left
+                    _ => lhs >> (i64::BITS - 1),
+                };
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+
+            Opcode::NegInt(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_int()?;
+                let result = operand
+                    .checked_neg()
+                    .ok_or(RuntimeErrorKind::IntegerOverflow)?;
+                self.registers[*res_idx as usize] = Value::Int(result);
+            }
+            Opcode::NegFloat(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Float(-operand);
+            }
+            Opcode::NegBool(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_bool()?;
+                self.registers[*res_idx as usize] = Value::Bool(!operand);
+            }
+            Opcode::BitNotInt(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Int(!operand);
+            }
 
-                Opcode::ModInt(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_int();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(lhs % rhs);
+            Opcode::IntToFloat(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_int()?;
+                self.registers[*res_idx as usize] = Value::Float(operand as f64);
+            }
+            Opcode::FloatToInt(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_float()?;
+                self.registers[*res_idx as usize] = Value::Int(operand as i64);
+            }
+            Opcode::IntToChar(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_int()?;
+                let Some(result) = u32::try_from(operand).ok().and_then(char::from_u32) else {
+                    return Err(RuntimeErrorKind::InvalidCodePoint(operand));
+                };
+                self.registers[*res_idx as usize] = Value::Char(result);
+            }
+            Opcode::CharToInt(operand_idx, res_idx) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_char()?;
+                self.registers[*res_idx as usize] = Value::Int(operand as i64);
+            }
+            Opcode::NewList(res_idx) => {
+                self.registers[*res_idx as usize] = Value::List(Rc::new(RefCell::new(Vec::new())));
+            }
+            Opcode::ListPush(list_idx, value_idx) => {
+                let value = self.registers[*value_idx as usize].clone();
+                self.registers[*list_idx as usize]
+                    .unwrap_list()?
+                    .borrow_mut()
+                    .push(value);
+            }
+            Opcode::ListPop(list_idx, res_idx) => {
+                let value = self.registers[*list_idx as usize]
+                    .unwrap_list()?
+                    .borrow_mut()
+                    .pop();
+                match value {
+                    Some(value) => self.registers[*res_idx as usize] = value,
+                    None => {
+                        return Err(RuntimeErrorKind::PopFromEmptyList);
+                    }
                 }
-                Opcode::ModFloat(lhs_idx, rhs_idx, res_idx) => {
-                    let lhs = self.registers[*lhs_idx as usize].unwrap_float();
-                    let rhs = self.registers[*rhs_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(lhs % rhs);
+            }
+            Opcode::ListGet(list_idx, index_idx, res_idx) => {
+                let index = self.registers[*index_idx as usize].unwrap_int()?;
+                let list = self.registers[*list_idx as usize].unwrap_list()?;
+                let len = list.borrow().len();
+                let value = match Self::checked_index(index, len) {
+                    Ok(index) => list.borrow()[index].clone(),
+                    Err(error) => {
+                        return Err(error);
+                    }
+                };
+                self.registers[*res_idx as usize] = value;
+            }
+            Opcode::ListSet(list_idx, index_idx, value_idx) => {
+                let index = self.registers[*index_idx as usize].unwrap_int()?;
+                let value = self.registers[*value_idx as usize].clone();
+                let list = self.registers[*list_idx as usize].unwrap_list()?;
+                let len = list.borrow().len();
+                match Self::checked_index(index, len) {
+                    Ok(index) => list.borrow_mut()[index] = value,
+                    Err(error) => {
+                        return Err(error);
+                    }
                 }
+            }
+            Opcode::ListLen(list_idx, res_idx) => {
+                let len = self.registers[*list_idx as usize]
+                    .unwrap_list()?
+                    .borrow()
+                    .len();
+                self.registers[*res_idx as usize] = Value::Int(len as i64);
+            }
 
-                Opcode::NegInt(operand_idx, res_idx) => {
-                    let operand = self.registers[*operand_idx as usize].unwrap_int();
-                    self.registers[*res_idx as usize] = Value::Int(-1 * operand);
-                }
-                Opcode::NegFloat(operand_idx, res_idx) => {
-                    let operand = self.registers[*operand_idx as usize].unwrap_float();
-                    self.registers[*res_idx as usize] = Value::Float(-1.0 * operand);
+            Opcode::NewMap(res_idx) => {
+                self.registers[*res_idx as usize] =
+                    Value::Map(Rc::new(RefCell::new(BTreeMap::new())));
+            }
+            Opcode::MapGet(map_idx, key_idx, res_idx) => {
+                let key = MapKey::from_value(&self.registers[*key_idx as usize])?;
+                let map = self.registers[*map_idx as usize].unwrap_map()?;
+                let value = map.borrow().get(&key).cloned();
+                match value {
+                    Some(value) => self.registers[*res_idx as usize] = value,
+                    None => {
+                        return Err(RuntimeErrorKind::KeyNotFound(key.to_string()));
+                    }
                 }
-                Opcode::NegBool(operand_idx, res_idx) => {
-                    let operand = self.registers[*operand_idx as usize].unwrap_bool();
-                    self.registers[*res_idx as usize] = Value::Bool(!operand);
+            }
+            Opcode::MapSet(map_idx, key_idx, value_idx) => {
+                let key = MapKey::from_value(&self.registers[*key_idx as usize])?;
+                let value = self.registers[*value_idx as usize].clone();
+                self.registers[*map_idx as usize]
+                    .unwrap_map()?
+                    .borrow_mut()
+                    .insert(key, value);
+            }
+            Opcode::MapContains(map_idx, key_idx, res_idx) => {
+                let key = MapKey::from_value(&self.registers[*key_idx as usize])?;
+                let contains = self.registers[*map_idx as usize]
+                    .unwrap_map()?
+                    .borrow()
+                    .contains_key(&key);
+                self.registers[*res_idx as usize] = Value::Bool(contains);
+            }
+            Opcode::MapRemove(map_idx, key_idx, res_idx) => {
+                let key = MapKey::from_value(&self.registers[*key_idx as usize])?;
+                let value = self.registers[*map_idx as usize]
+                    .unwrap_map()?
+                    .borrow_mut()
+                    .remove(&key);
+                match value {
+                    Some(value) => self.registers[*res_idx as usize] = value,
+                    None => {
+                        return Err(RuntimeErrorKind::KeyNotFound(key.to_string()));
+                    }
                 }
+            }
+            Opcode::MapKeys(map_idx, res_idx) => {
+                let keys: Vec<Value> = self.registers[*map_idx as usize]
+                    .unwrap_map()?
+                    .borrow()
+                    .keys()
+                    .map(MapKey::to_value)
+                    .collect();
+                self.registers[*res_idx as usize] = Value::List(Rc::new(RefCell::new(keys)));
+            }
+            Opcode::MapLen(map_idx, res_idx) => {
+                let len = self.registers[*map_idx as usize]
+                    .unwrap_map()?
+                    .borrow()
+                    .len();
+                self.registers[*res_idx as usize] = Value::Int(len as i64);
+            }
 
-                Opcode::LoadConst(_target_idx, _pool_idx) => {
-                    todo!();
-                }
-                Opcode::LoadNum(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Int(*value as i64);
+            Opcode::NewStruct(res_idx, field_count) => {
+                let fields = vec![Value::Int(0); *field_count as usize];
+                self.registers[*res_idx as usize] =
+                    Value::Struct(Rc::new(RefCell::new(fields.into_boxed_slice())));
+            }
+            Opcode::StructGet(struct_idx, field_idx, res_idx) => {
+                let value = self.registers[*struct_idx as usize]
+                    .unwrap_struct()?
+                    .borrow()
+                    .get(*field_idx as usize)
+                    .cloned()
+                    .ok_or(RuntimeErrorKind::InvalidBytecode)?;
+                self.registers[*res_idx as usize] = value;
+            }
+            Opcode::StructSet(struct_idx, field_idx, value_idx) => {
+                let value = self.registers[*value_idx as usize].clone();
+                let fields = self.registers[*struct_idx as usize].unwrap_struct()?;
+                match fields.borrow_mut().get_mut(*field_idx as usize) {
+                    Some(field) => *field = value,
+                    None => return Err(RuntimeErrorKind::InvalidBytecode),
                 }
-                Opcode::Copy(source_idx, dest_idx) => {
-                    self.registers[*dest_idx as usize] =
-                        self.registers[*source_idx as usize].clone();
+            }
+
+            Opcode::ToStr(operand_idx, res_idx) => {
+                let result = self.registers[*operand_idx as usize].to_string();
+                self.registers[*res_idx as usize] = Value::Str(Rc::new(result));
+            }
+            Opcode::AppendStr(res_idx, value_idx) => {
+                let value = self.registers[*value_idx as usize].unwrap_string()?.clone();
+                match &mut self.registers[*res_idx as usize] {
+                    // Only copies the string if it is shared, e.g. with the constant pool
+                    Value::Str(string) => Rc::make_mut(string).push_str(&value),
+                    other => {
+                        return Err(RuntimeErrorKind::TypeMismatch("string", other.type_name()));
+                    }
                 }
+            }
+
+            Opcode::LoadConst(target_idx, pool_idx) => {
+                let constant = self.constants.get(*pool_idx as usize);
+                self.registers[*target_idx as usize] =
+                    constant.ok_or(RuntimeErrorKind::InvalidBytecode)?.clone();
+            }
+            Opcode::LoadNum(target_idx, value) => {
+                self.registers[*target_idx as usize] = Value::Int(*value as i64);
+            }
+            Opcode::LoadBool(target_idx, value) => {
+                self.registers[*target_idx as usize] = Value::Bool(*value);
+            }
+            Opcode::Copy(source_idx, dest_idx) => {
+                self.registers[*dest_idx as usize] = self.registers[*source_idx as usize].clone();
+            }
 
-                Opcode::Save(source_reg) => {
-                    self.return_value = Some(self.registers[*source_reg as usize].clone());
+            Opcode::Save(source_reg) => {
+                self.return_value = Some(self.registers[*source_reg as usize].clone());
+            }
+            Opcode::Jump(amount) => {
+                self.program_counter = self.program_counter.wrapping_add_signed(*amount as isize);
+            }
+            Opcode::JumpCond(operand_idx, amount) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_bool()?;
+                if !operand {
+                    self.program_counter =
+                        self.program_counter.wrapping_add_signed(*amount as isize);
                 }
-                Opcode::Jump(amount) => {
+            }
+            Opcode::JumpCondTrue(operand_idx, amount) => {
+                let operand = self.registers[*operand_idx as usize].unwrap_bool()?;
+                if operand {
                     self.program_counter =
                         self.program_counter.wrapping_add_signed(*amount as isize);
                 }
-                Opcode::JumpCond(operand_idx, amount) => {
-                    let operand = self.registers[*operand_idx as usize].unwrap_bool();
-                    if !operand {
-                        self.program_counter =
-                            self.program_counter.wrapping_add_signed(*amount as isize);
+            }
+            Opcode::PushArg(source_idx) => {
+                self.arguments
+                    .push(self.registers[*source_idx as usize].clone());
+            }
+            Opcode::Call(address, arg_count, res_idx) => {
+                let (address, arg_count, res_idx) = (*address, *arg_count, *res_idx);
+                let registers = self.take_arguments(arg_count)?;
+                self.enter_function(registers, address, res_idx)?;
+                return Ok(());
+            }
+            Opcode::Closure(res_idx, address) => {
+                self.registers[*res_idx as usize] = Value::Function(Rc::new(Closure {
+                    address: *address,
+                    captures: Vec::new(),
+                }));
+            }
+            Opcode::Capture(closure_idx, src_idx, slot_idx) => {
+                let value = self.registers[*src_idx as usize].clone();
+                match &mut self.registers[*closure_idx as usize] {
+                    Value::Function(closure) => {
+                        Rc::make_mut(closure).captures.push((*slot_idx, value))
+                    }
+                    other => {
+                        return Err(RuntimeErrorKind::TypeMismatch(
+                            "function",
+                            other.type_name(),
+                        ));
                     }
                 }
-                Opcode::Error => {
-                    panic!("Internal error");
+            }
+            Opcode::CallValue(closure_idx, arg_count, res_idx) => {
+                let closure = self.registers[*closure_idx as usize].unwrap_function()?;
+                let (arg_count, res_idx) = (*arg_count, *res_idx);
+                let mut registers = self.take_arguments(arg_count)?;
+                for (slot_idx, value) in &closure.captures {
+                    registers[*slot_idx as usize] = value.clone();
                 }
+                self.enter_function(registers, closure.address, res_idx)?;
+                return Ok(());
+            }
+            Opcode::Return(source_idx) => {
+                let value = self.registers[*source_idx as usize].clone();
+                let frame = self
+                    .call_stack
+                    .pop()
+                    .ok_or(RuntimeErrorKind::InvalidBytecode)?;
+                self.registers = frame.registers;
+                self.registers[frame.result_register as usize] = value;
+                self.program_counter = frame.return_address;
+            }
+            Opcode::ReturnVoid => {
+                let frame = self
+                    .call_stack
+                    .pop()
+                    .ok_or(RuntimeErrorKind::InvalidBytecode)?;
+                self.registers = frame.registers;
+                self.program_counter = frame.return_address;
+            }
+            Opcode::Error => return Err(RuntimeErrorKind::InvalidBytecode),
 
-                // For WIP only
-                Opcode::LoadInt(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Int(*value);
-                }
-                Opcode::LoadFloat(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Float(*value);
-                }
-                Opcode::LoadBool(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Bool(*value);
+            Opcode::Print(src_idx) => {
+                let value = &self.registers[*src_idx as usize];
+                // Flushing makes text without a line break visible, e.g. a prompt before read_line
+                let result = write!(self.output, "{value}").and_then(|_| self.output.flush());
+                if let Err(error) = result {
+                    return Err(RuntimeErrorKind::Io(error.to_string()));
                 }
-                Opcode::LoadStr(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Str(Box::new(*value.clone()));
-                }
-                Opcode::LoadChar(target_idx, value) => {
-                    self.registers[*target_idx as usize] = Value::Char(*value);
-                }
-                Opcode::Print(target_idx) => {
-                    println!("{}", self.registers[*target_idx as usize]);
+            }
+            Opcode::PrintLn(src_idx) => {
+                let value = &self.registers[*src_idx as usize];
+                if let Err(error) = writeln!(self.output, "{value}") {
+                    return Err(RuntimeErrorKind::Io(error.to_string()));
                 }
             }
-            self.program_counter = self.program_counter.wrapping_add(1);
+            Opcode::ReadLine(res_idx) => match Self::read_line(&mut self.input) {
+                Ok(line) => self.registers[*res_idx as usize] = Value::Str(Rc::new(line)),
+                Err(error) => {
+                    return Err(RuntimeErrorKind::Io(error.to_string()));
+                }
+            },
         }
+        self.program_counter = self.program_counter.wrapping_add(1);
+        Ok(())
     }
 }
 
@@ -317,60 +911,306 @@ pub enum Value {
     Int(i64),
     Float(f64),
     Bool(bool),
-    Str(Box<String>),
+    // Strings are immutable once shared, so copies of a constant are cheap
+    Str(Rc<String>),
     Char(char),
+    Function(Rc<Closure>),
+    // Lists are shared between every register holding them
+    List(Rc<RefCell<Vec<Value>>>),
+    // Maps are shared like lists and kept sorted by key, so iterating them is deterministic
+    Map(Rc<RefCell<BTreeMap<MapKey, Value>>>),
+    // Struct fields in declaration order, shared like lists
+    Struct(Rc<RefCell<Box<[Value]>>>),
+}
+
+// Value types which can be used as map keys
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MapKey {
+    Int(i64),
+    Str(String),
+}
+
+impl MapKey {
+    #[inline]
+    fn from_value(value: &Value) -> Result<MapKey, RuntimeErrorKind> {
+        match value {
+            Value::Int(v) => Ok(MapKey::Int(*v)),
+            Value::Str(v) => Ok(MapKey::Str(v.to_string())),
+            _ => Err(RuntimeErrorKind::TypeMismatch(
+                "int or string",
+                value.type_name(),
+            )),
+        }
+    }
+
+    #[inline]
+    fn to_value(&self) -> Value {
+        match self {
+            MapKey::Int(v) => Value::Int(*v),
+            MapKey::Str(v) => Value::Str(Rc::new(v.clone())),
+        }
+    }
+}
+
+impl std::fmt::Display for MapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MapKey::Int(value) => write!(f, "{}", value),
+            MapKey::Str(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Closure {
+    address: u32,
+    // Values placed into the given registers of the callee's window on every call
+    captures: Vec<(u8, Value)>,
 }
 
 impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::Char(_) => "char",
+            Value::Function(_) => "function",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Struct(_) => "struct",
+        }
+    }
+
     #[inline]
-    fn unwrap_int(&self) -> i64 {
+    fn unwrap_int(&self) -> Result<i64, RuntimeErrorKind> {
         match self {
-            Value::Int(v) => *v,
-            _ => panic!("Internal type error"),
+            Value::Int(v) => Ok(*v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("int", self.type_name())),
         }
     }
 
     #[inline]
-    fn unwrap_float(&self) -> f64 {
+    fn unwrap_float(&self) -> Result<f64, RuntimeErrorKind> {
         match self {
-            Value::Float(v) => *v,
-            _ => panic!("Internal type error"),
+            Value::Float(v) => Ok(*v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("float", self.type_name())),
         }
     }
 
     #[inline]
-    fn unwrap_bool(&self) -> bool {
+    fn unwrap_bool(&self) -> Result<bool, RuntimeErrorKind> {
         match self {
-            Value::Bool(v) => *v,
-            _ => panic!("Internal type error"),
+            Value::Bool(v) => Ok(*v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("bool", self.type_name())),
         }
     }
 
     #[inline]
-    fn unwrap_string(&self) -> &String {
+    fn unwrap_string(&self) -> Result<&String, RuntimeErrorKind> {
         match self {
-            Value::Str(v) => v,
-            _ => panic!("Internal type error"),
+            Value::Str(v) => Ok(v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("string", self.type_name())),
         }
     }
 
     #[inline]
-    fn unwrap_char(&self) -> char {
+    fn unwrap_char(&self) -> Result<char, RuntimeErrorKind> {
         match self {
-            Value::Char(v) => *v,
-            _ => panic!("Internal type error"),
+            Value::Char(v) => Ok(*v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("char", self.type_name())),
+        }
+    }
+
+    #[inline]
+    fn unwrap_list(&self) -> Result<&Rc<RefCell<Vec<Value>>>, RuntimeErrorKind> {
+        match self {
+            Value::List(v) => Ok(v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("list", self.type_name())),
+        }
+    }
+
+    #[inline]
+    fn unwrap_map(&self) -> Result<&Rc<RefCell<BTreeMap<MapKey, Value>>>, RuntimeErrorKind> {
+        match self {
+            Value::Map(v) => Ok(v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("map", self.type_name())),
+        }
+    }
+
+    #[inline]
+    fn unwrap_struct(&self) -> Result<&Rc<RefCell<Box<[Value]>>>, RuntimeErrorKind> {
+        match self {
+            Value::Struct(v) => Ok(v),
+            _ => Err(RuntimeErrorKind::TypeMismatch("struct", self.type_name())),
+        }
+    }
+
+    #[inline]
+    fn unwrap_function(&self) -> Result<Rc<Closure>, RuntimeErrorKind> {
+        match self {
+            Value::Function(v) => Ok(v.clone()),
+            _ => Err(RuntimeErrorKind::TypeMismatch("function", self.type_name())),
         }
     }
 }
 
 impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl Value {
+    // Lists, maps and structs are shared, so a value can contain itself. The containers which are
+    // currently being printed are tracked to print such a reference as '<cycle>' instead of recursing forever.
+    fn fmt_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        ancestors: &mut Vec<*const ()>,
+    ) -> std::fmt::Result {
+        let pointer = match self {
+            Value::List(list) => Rc::as_ptr(list) as *const (),
+            Value::Map(map) => Rc::as_ptr(map) as *const (),
+            Value::Struct(fields) => Rc::as_ptr(fields) as *const (),
+            Value::Int(value) => return write!(f, "{}", value),
+            Value::Float(value) => return write!(f, "{}", value),
+            Value::Bool(value) => return write!(f, "{}", value),
+            Value::Str(value) => return write!(f, "{}", value),
+            Value::Char(value) => return write!(f, "{}", value),
+            Value::Function(closure) => return write!(f, "fn@{}", closure.address),
+        };
+        if ancestors.contains(&pointer) {
+            return write!(f, "<cycle>");
+        }
+        ancestors.push(pointer);
+        let result = match self {
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (idx, element) in list.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.fmt_nested(f, ancestors)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (idx, (key, value)) in map.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: ")?;
+                    value.fmt_nested(f, ancestors)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Struct(fields) => {
+                write!(f, "{{")?;
+                for (idx, field) in fields.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    field.fmt_nested(f, ancestors)?;
+                }
+                write!(f, "}}")
+            }
+            _ => unreachable!(),
+        };
+        ancestors.pop();
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeErrorKind {
+    IndexOutOfBounds(i64, usize),
+    PopFromEmptyList,
+    KeyNotFound(String),
+    DivisionByZero,
+    IntegerOverflow,
+    NegativeExponent(i64),
+    // A surrogate, a negative number or anything above 0x10FFFF cast to a char
+    InvalidCodePoint(i64),
+    Io(String),
+    // The expected and the actual type of an operand, which the compiler should have ruled out
+    TypeMismatch(&'static str, &'static str),
+    InvalidBytecode,
+    // Too many nested function calls, usually because of runaway recursion
+    StackOverflow,
+    // The instruction budget set with Thread::set_fuel is used up, execution can be resumed
+    OutOfFuel,
+}
+
+impl std::fmt::Display for RuntimeErrorKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::Int(value) => write!(f, "{}", value),
-            Value::Float(value) => write!(f, "{}", value),
-            Value::Bool(value) => write!(f, "{}", value),
-            Value::Str(value) => write!(f, "{}", value),
-            Value::Char(value) => write!(f, "{}", value),
+            RuntimeErrorKind::IndexOutOfBounds(index, len) => {
+                write!(
+                    f,
+                    "Index {} is out of bounds for a list of length {}",
+                    index, len
+                )
+            }
+            RuntimeErrorKind::PopFromEmptyList => write!(f, "Cannot pop from an empty list"),
+            RuntimeErrorKind::KeyNotFound(key) => write!(f, "Key '{}' is not in the map", key),
+            RuntimeErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeErrorKind::IntegerOverflow => write!(f, "Integer overflow"),
+            RuntimeErrorKind::NegativeExponent(exponent) => {
+                write!(f, "Cannot raise an int to the negative power {}", exponent)
+            }
+            RuntimeErrorKind::InvalidCodePoint(code_point) => {
+                write!(f, "{} is not a valid char code point", code_point)
+            }
+            RuntimeErrorKind::Io(error) => write!(f, "I/O error: {}", error),
+            RuntimeErrorKind::TypeMismatch(expected, found) => {
+                write!(
+                    f,
+                    "Expected a value of type '{}', found '{}'",
+                    expected, found
+                )
+            }
+            RuntimeErrorKind::InvalidBytecode => write!(f, "Invalid bytecode"),
+            RuntimeErrorKind::StackOverflow => {
+                write!(
+                    f,
+                    "Stack overflow, more than {} nested calls",
+                    MAX_CALL_DEPTH
+                )
+            }
+            RuntimeErrorKind::OutOfFuel => write!(f, "Ran out of fuel"),
         }
     }
 }
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeError {
+    kind: RuntimeErrorKind,
+    // Index of the instruction which failed
+    program_counter: usize,
+}
+
+impl RuntimeError {
+    pub fn new(kind: RuntimeErrorKind, program_counter: usize) -> Self {
+        RuntimeError {
+            kind,
+            program_counter,
+        }
+    }
+
+    pub fn kind(&self) -> &RuntimeErrorKind {
+        &self.kind
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at instruction {})", self.kind, self.program_counter)
+    }
+}
@@ -1,39 +1,47 @@
 mod compiler;
+mod debugger;
 mod interpreter;
 mod opcode;
+mod program;
 
 #[cfg(test)]
 mod tests;
 
-use compiler::Compiler;
-use interpreter::Thread;
 use std::io::Write;
 
+// Lets a host embed the language, e.g. to run scripts with their own I/O or instruction budget
+pub use compiler::Compiler;
+pub use debugger::Debugger;
+pub use interpreter::{RuntimeError, RuntimeErrorKind, Status, Thread, Value};
+pub use program::Program;
+
 pub fn lib_main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 3 {
-        eprintln!("Unrecognized command line arguments");
-        std::process::exit(1);
-    } else if args.len() == 3 {
-        if args[1] == "--asm" {
-            process_file(args[2].as_str(), true);
-        } else {
-            eprintln!("Unrecognized command line arguments");
-            std::process::exit(1);
-        }
-    } else if args.len() == 2 {
-        if args[1] == "--asm" {
-            interactive(true);
-        } else {
-            process_file(args[1].as_str(), false);
+    let mut print_bytecode = false;
+    let mut int_promotion = false;
+    let mut debug = false;
+    let mut file_path: Option<String> = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--asm" => print_bytecode = true,
+            "--promote" => int_promotion = true,
+            "--debug" => debug = true,
+            _ if file_path.is_none() && !arg.starts_with("--") => file_path = Some(arg),
+            _ => {
+                eprintln!("Unrecognized command line arguments");
+                std::process::exit(1);
+            }
         }
-    } else {
-        interactive(false);
+    }
+
+    match file_path {
+        Some(file_path) => process_file(file_path.as_str(), print_bytecode, int_promotion, debug),
+        None => interactive(print_bytecode, int_promotion, debug),
     }
 }
 
-pub fn interactive(print_bytecode: bool) {
+pub fn interactive(print_bytecode: bool, int_promotion: bool, debug: bool) {
     let mut compiler = Compiler::new();
+    compiler.set_int_promotion(int_promotion);
     let mut input = String::new();
     loop {
         print!(">>> ");
@@ -57,33 +65,39 @@ pub fn interactive(print_bytecode: bool) {
         }
 
         if !input.is_empty() {
-            process(&mut compiler, &input, print_bytecode);
+            process(&mut compiler, &input, print_bytecode, debug);
         }
 
         input.clear();
     }
 }
 
-fn process(compiler: &mut Compiler, input: &str, print_bytecode: bool) {
+fn process(compiler: &mut Compiler, input: &str, print_bytecode: bool, debug: bool) {
     match compiler.compile(input, "stdin") {
-        Ok(bytecode) => {
+        Ok(program) => {
             if print_bytecode {
-                for item in &bytecode {
-                    println!("{item}");
-                }
+                print!("{program}");
+            }
+            let mut thread = Thread::new(program);
+            if debug {
+                Debugger::new(thread)
+                    .run()
+                    .expect("Failed to communicate with the debugger");
+                return;
             }
-            let mut thread = Thread::new(bytecode);
-            thread.exec();
-            if let Some(val) = thread.return_value() {
-                println!("{val}");
+            match thread.exec() {
+                Ok(Some(val)) => println!("{val}"),
+                Ok(None) => (),
+                Err(error) => println!("{}", thread.source_map().format_error(&error)),
             }
         }
         Err(e) => println!("{e}"),
     }
 }
 
-fn process_file(file_path: &str, print_bytecode: bool) {
+fn process_file(file_path: &str, print_bytecode: bool, int_promotion: bool, debug: bool) {
     let mut compiler = Compiler::new();
+    compiler.set_int_promotion(int_promotion);
     let input = match std::fs::read_to_string(file_path) {
         Ok(text) => text,
         Err(e) => {
@@ -91,5 +105,5 @@ fn process_file(file_path: &str, print_bytecode: bool) {
             std::process::exit(1);
         }
     };
-    process(&mut compiler, input.as_str(), print_bytecode);
+    process(&mut compiler, input.as_str(), print_bytecode, debug);
 }
@@ -51,29 +51,76 @@ pub enum Opcode {
     DivInt(u8, u8, u8),   // lhs idx, rhs idx, result idx
     DivFloat(u8, u8, u8), // lhs idx, rhs idx, result idx
 
+    // Rounds towards negative infinity, e.g. -7 // 2 == -4
+    FloorDivInt(u8, u8, u8),   // lhs idx, rhs idx, result idx
+    FloorDivFloat(u8, u8, u8), // lhs idx, rhs idx, result idx
+
+    // Takes the sign of the divisor to match the floor division, e.g. -7 % 2 == 1
     ModInt(u8, u8, u8),   // lhs idx, rhs idx, result idx
     ModFloat(u8, u8, u8), // lhs idx, rhs idx, result idx
 
-    NegInt(u8, u8),   // operand idx, result idx
-    NegFloat(u8, u8), // operand idx, result idx
-    NegBool(u8, u8),  // operand idx, result idx
+    PowInt(u8, u8, u8),   // base idx, exponent idx, result idx
+    PowFloat(u8, u8, u8), // base idx, exponent idx, result idx
+
+    BitAndInt(u8, u8, u8), // lhs idx, rhs idx, result idx
+    BitOrInt(u8, u8, u8),  // lhs idx, rhs idx, result idx
+    BitXorInt(u8, u8, u8), // lhs idx, rhs idx, result idx
+    // Shift amounts outside of 0..64 shift out every bit, leaving 0, or -1 for '>>' of a negative value
+    ShiftLeftInt(u8, u8, u8),  // lhs idx, rhs idx, result idx
+    ShiftRightInt(u8, u8, u8), // lhs idx, rhs idx, result idx - Arithmetic shift
+
+    NegInt(u8, u8),    // operand idx, result idx
+    NegFloat(u8, u8),  // operand idx, result idx
+    NegBool(u8, u8),   // operand idx, result idx
+    BitNotInt(u8, u8), // operand idx, result idx
+
+    IntToFloat(u8, u8), // operand idx, result idx
+    FloatToInt(u8, u8), // operand idx, result idx - Truncates towards zero, saturating at the bounds
+    IntToChar(u8, u8),  // operand idx, result idx - Fails for an invalid code point
+    CharToInt(u8, u8),  // operand idx, result idx
+    ToStr(u8, u8),      // operand idx, result idx - Works for a value of any type
+    AppendStr(u8, u8),  // string idx, value idx - Appends the string value in place
+
+    NewList(u8),         // result idx - Create an empty list
+    ListPush(u8, u8),    // list idx, value idx
+    ListPop(u8, u8),     // list idx, result idx
+    ListGet(u8, u8, u8), // list idx, index idx, result idx
+    ListSet(u8, u8, u8), // list idx, index idx, value idx
+    ListLen(u8, u8),     // list idx, result idx
+
+    NewMap(u8),              // result idx - Create an empty map
+    MapGet(u8, u8, u8),      // map idx, key idx, result idx
+    MapSet(u8, u8, u8),      // map idx, key idx, value idx - Inserts the key if it is missing
+    MapContains(u8, u8, u8), // map idx, key idx, result idx
+    MapRemove(u8, u8, u8),   // map idx, key idx, result idx - Result is the removed value
+    MapKeys(u8, u8),         // map idx, result idx - List of the keys in ascending order
+    MapLen(u8, u8),          // map idx, result idx
+
+    NewStruct(u8, u8), // result idx, field count - Fields must be set before they are read
+    StructGet(u8, u8, u8), // struct idx, field idx, result idx
+    StructSet(u8, u8, u8), // struct idx, field idx, value idx
 
     LoadConst(u8, u16), // target register, constant pool idx
     LoadNum(u8, i16),   // target register, small numeric constant value
     LoadBool(u8, bool), // target register, bool constant
     Copy(u8, u8),       // src idx, dst idx
 
-    Save(u8),          // Save content of target register as thread return value
-    Jump(i16),         // Offset amount
+    Save(u8),              // Save content of target register as thread return value
+    Jump(i16),             // Offset amount
     JumpCond(u8, i16), // operand idx, amount - Conditional jump based on the content of the register
-    Error,             // Malformed bytecode
-
-    // For WIP only
-    LoadInt(u8, i64),
-    LoadFloat(u8, f64),
-    LoadStr(u8, Box<String>),
-    LoadChar(u8, char),
-    Print(u8), // argument idx
+    JumpCondTrue(u8, i16), // operand idx, amount - Like JumpCond, but jumps if the register holds true
+    PushArg(u8), // argument idx - Queue the content of the register as an argument for the next call
+    Call(u32, u8, u8), // function address, argument count, result idx
+    Return(u8),  // Return the content of the register to the caller
+    ReturnVoid,  // Return to the caller without a value
+    Closure(u8, u32), // result idx, function address - Create a function value without captures
+    Capture(u8, u8, u8), // closure idx, src idx, slot idx - Capture the content of src into the slot of the callee's window
+    CallValue(u8, u8, u8), // closure idx, argument count, result idx
+    Error,               // Malformed bytecode
+
+    Print(u8),    // argument idx
+    PrintLn(u8),  // argument idx
+    ReadLine(u8), // result idx - Reads a line from the input without its line ending
 }
 
 impl std::fmt::Display for Opcode {
@@ -203,6 +250,13 @@ impl std::fmt::Display for Opcode {
                 write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "div_float")
             }
 
+            Opcode::FloorDivInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "fdiv_int")
+            }
+            Opcode::FloorDivFloat(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "fdiv_float")
+            }
+
             Opcode::ModInt(lhs, rhs, dst) => {
                 write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "mod_int")
             }
@@ -210,6 +264,29 @@ impl std::fmt::Display for Opcode {
                 write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "mod_float")
             }
 
+            Opcode::PowInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "pow_int")
+            }
+            Opcode::PowFloat(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "pow_float")
+            }
+
+            Opcode::BitAndInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "bitand_int")
+            }
+            Opcode::BitOrInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "bitor_int")
+            }
+            Opcode::BitXorInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "bitxor_int")
+            }
+            Opcode::ShiftLeftInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "shl_int")
+            }
+            Opcode::ShiftRightInt(lhs, rhs, dst) => {
+                write!(f, "{:<padding$} {lhs:<3} {rhs:<3} {dst:<3}", "shr_int")
+            }
+
             Opcode::NegInt(operand, dst) => {
                 write!(f, "{:<padding$} {operand:<3} {dst:<3}", "neg_int")
             }
@@ -219,6 +296,75 @@ impl std::fmt::Display for Opcode {
             Opcode::NegBool(operand, dst) => {
                 write!(f, "{:<padding$} {operand:<3} {dst:<3}", "neg_bool")
             }
+            Opcode::BitNotInt(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "bitnot_int")
+            }
+
+            Opcode::IntToFloat(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "int_to_float")
+            }
+            Opcode::FloatToInt(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "float_to_int")
+            }
+            Opcode::IntToChar(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "int_to_char")
+            }
+            Opcode::CharToInt(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "char_to_int")
+            }
+            Opcode::ToStr(operand, dst) => {
+                write!(f, "{:<padding$} {operand:<3} {dst:<3}", "to_str")
+            }
+            Opcode::AppendStr(dst, src) => {
+                write!(f, "{:<padding$} {dst:<3} {src:<3}", "append_str")
+            }
+
+            Opcode::NewList(dst) => write!(f, "{:<padding$} {dst:<3}", "list_new"),
+            Opcode::ListPush(list, src) => {
+                write!(f, "{:<padding$} {list:<3} {src:<3}", "list_push")
+            }
+            Opcode::ListPop(list, dst) => write!(f, "{:<padding$} {list:<3} {dst:<3}", "list_pop"),
+            Opcode::ListGet(list, index, dst) => {
+                write!(f, "{:<padding$} {list:<3} {index:<3} {dst:<3}", "list_get")
+            }
+            Opcode::ListSet(list, index, src) => {
+                write!(f, "{:<padding$} {list:<3} {index:<3} {src:<3}", "list_set")
+            }
+            Opcode::ListLen(list, dst) => write!(f, "{:<padding$} {list:<3} {dst:<3}", "list_len"),
+
+            Opcode::NewMap(dst) => write!(f, "{:<padding$} {dst:<3}", "map_new"),
+            Opcode::MapGet(map, key, dst) => {
+                write!(f, "{:<padding$} {map:<3} {key:<3} {dst:<3}", "map_get")
+            }
+            Opcode::MapSet(map, key, src) => {
+                write!(f, "{:<padding$} {map:<3} {key:<3} {src:<3}", "map_set")
+            }
+            Opcode::MapContains(map, key, dst) => {
+                write!(f, "{:<padding$} {map:<3} {key:<3} {dst:<3}", "map_contains")
+            }
+            Opcode::MapRemove(map, key, dst) => {
+                write!(f, "{:<padding$} {map:<3} {key:<3} {dst:<3}", "map_remove")
+            }
+            Opcode::MapKeys(map, dst) => write!(f, "{:<padding$} {map:<3} {dst:<3}", "map_keys"),
+            Opcode::MapLen(map, dst) => write!(f, "{:<padding$} {map:<3} {dst:<3}", "map_len"),
+
+            Opcode::NewStruct(dst, count) => {
+                write!(f, "{:<padding$} {dst:<3} {count}", "struct_new")
+            }
+            Opcode::StructGet(object, field, dst) => {
+                write!(
+                    f,
+                    "{:<padding$} {object:<3} {field:<3} {dst:<3}",
+                    "struct_get"
+                )
+            }
+            Opcode::StructSet(object, field, src) => {
+                write!(
+                    f,
+                    "{:<padding$} {object:<3} {field:<3} {src:<3}",
+                    "struct_set"
+                )
+            }
 
             Opcode::LoadConst(reg, idx) => write!(f, "{:<padding$} {reg:<3} {idx}", "ldconst"),
             Opcode::LoadNum(reg, val) => write!(f, "{:<padding$} {reg:<3} {val}", "ldnum"),
@@ -230,16 +376,37 @@ impl std::fmt::Display for Opcode {
             Opcode::JumpCond(operand, amount) => {
                 write!(f, "{:<padding$} {operand:<3} {amount}", "jumpcond")
             }
+            Opcode::JumpCondTrue(operand, amount) => {
+                write!(f, "{:<padding$} {operand:<3} {amount}", "jumpcondtrue")
+            }
+            Opcode::PushArg(reg) => write!(f, "{:<padding$} {reg:<3}", "pusharg"),
+            Opcode::Call(address, arg_count, dst) => {
+                write!(
+                    f,
+                    "{:<padding$} {address:<3} {arg_count:<3} {dst:<3}",
+                    "call"
+                )
+            }
+            Opcode::Return(reg) => write!(f, "{:<padding$} {reg:<3}", "ret"),
+            Opcode::ReturnVoid => write!(f, "{:<padding$}", "ret_void"),
+            Opcode::Closure(dst, address) => {
+                write!(f, "{:<padding$} {dst:<3} {address}", "closure")
+            }
+            Opcode::Capture(closure, src, slot) => {
+                write!(f, "{:<padding$} {closure:<3} {src:<3} {slot:<3}", "capture")
+            }
+            Opcode::CallValue(closure, arg_count, dst) => {
+                write!(
+                    f,
+                    "{:<padding$} {closure:<3} {arg_count:<3} {dst:<3}",
+                    "callval"
+                )
+            }
             Opcode::Error => write!(f, "{:<padding$}", "error"),
 
-            // For WIP only
-            Opcode::LoadInt(reg, val) => write!(f, "{:<padding$} {reg:<3} {val}", "wip_loadint"),
-            Opcode::LoadFloat(reg, val) => {
-                write!(f, "{:<padding$} {reg:<3} {val}", "wip_loadfloat")
-            }
-            Opcode::LoadStr(reg, val) => write!(f, "{:<padding$} {reg:<3} {val}", "wip_loadstr"),
-            Opcode::LoadChar(reg, val) => write!(f, "{:<padding$} {reg:<3} {val}", "wip_loadchar"),
-            Opcode::Print(reg) => write!(f, "{:<padding$} {reg:<3}", "wip_print"),
+            Opcode::Print(reg) => write!(f, "{:<padding$} {reg:<3}", "print"),
+            Opcode::PrintLn(reg) => write!(f, "{:<padding$} {reg:<3}", "println"),
+            Opcode::ReadLine(reg) => write!(f, "{:<padding$} {reg:<3}", "read_line"),
         }
     }
 }
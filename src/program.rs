@@ -0,0 +1,105 @@
+use crate::compiler::SourceMap;
+use crate::interpreter::Value;
+use crate::opcode::Opcode;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Compiled bytecode together with the constants it loads through LoadConst
+#[derive(Debug)]
+pub struct Program {
+    instructions: Vec<Opcode>,
+    constants: Vec<Value>,
+    source_map: SourceMap,
+}
+
+impl Program {
+    pub fn new(instructions: Vec<Opcode>, constants: Vec<Value>, source_map: SourceMap) -> Self {
+        Program {
+            instructions,
+            constants,
+            source_map,
+        }
+    }
+
+    pub fn into_parts(self) -> (Vec<Opcode>, Vec<Value>, SourceMap) {
+        (self.instructions, self.constants, self.source_map)
+    }
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for instruction in &self.instructions {
+            writeln!(f, "{instruction}")?;
+        }
+        if !self.constants.is_empty() {
+            writeln!(f, "constants:")?;
+        }
+        for (idx, constant) in self.constants.iter().enumerate() {
+            match constant {
+                Value::Str(value) => writeln!(f, "{idx:<3} {value:?}")?,
+                Value::Char(value) => writeln!(f, "{idx:<3} {value:?}")?,
+                _ => writeln!(f, "{idx:<3} {constant}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Hashable form of a constant, floats are compared by their bits
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Int(i64),
+    Float(u64),
+    Str(Rc<String>),
+    Char(char),
+}
+
+// Value which can be stored in the constant pool
+#[derive(Clone, Debug)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Str(Rc<String>),
+    Char(char),
+}
+
+// Returned when a program has more distinct constants than LoadConst can address
+#[derive(Debug)]
+pub struct ConstantPoolFull;
+
+// Collects the constants of a program while it is compiled, storing each distinct value once
+#[derive(Debug, Default)]
+pub struct ConstantPool {
+    constants: Vec<Value>,
+    indices: HashMap<ConstantKey, u16>,
+}
+
+impl ConstantPool {
+    // Returns the pool index of the constant, adding it if it is not in the pool yet
+    pub fn add(&mut self, constant: Constant) -> Result<u16, ConstantPoolFull> {
+        let (key, value) = match constant {
+            Constant::Int(v) => (ConstantKey::Int(v), Value::Int(v)),
+            Constant::Float(v) => (ConstantKey::Float(v.to_bits()), Value::Float(v)),
+            Constant::Str(v) => (ConstantKey::Str(v.clone()), Value::Str(v)),
+            Constant::Char(v) => (ConstantKey::Char(v), Value::Char(v)),
+        };
+        if let Some(idx) = self.indices.get(&key) {
+            return Ok(*idx);
+        }
+        let idx = u16::try_from(self.constants.len()).map_err(|_| ConstantPoolFull)?;
+        self.constants.push(value);
+        self.indices.insert(key, idx);
+        Ok(idx)
+    }
+
+    pub fn clear(&mut self) {
+        self.constants.clear();
+        self.indices.clear();
+    }
+
+    pub fn take(&mut self) -> Vec<Value> {
+        self.indices.clear();
+        std::mem::take(&mut self.constants)
+    }
+}
@@ -1,5 +1,12 @@
 use crate::Compiler;
-use crate::interpreter::{Thread, Value};
+use crate::compiler::SourceMap;
+use crate::debugger::Debugger;
+use crate::interpreter::{RuntimeErrorKind, Status, Thread, Value};
+use crate::opcode::Opcode;
+use crate::program::Program;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
 
 fn process_and_unwrap_expression(compiler: &mut Compiler, input: &str) -> Value {
     let input = format!("return {input};");
@@ -7,13 +14,81 @@ fn process_and_unwrap_expression(compiler: &mut Compiler, input: &str) -> Value
     match compiler.compile(&input, "stdin") {
         Ok(bytecode) => {
             let mut thread = Thread::new(bytecode);
-            thread.exec();
-            thread.return_value().clone().unwrap()
+            thread.exec().unwrap().unwrap()
         }
         Err(e) => panic!("{}", e.as_str()),
     }
 }
 
+fn process_and_unwrap_program(compiler: &mut Compiler, input: &str) -> Value {
+    match compiler.compile(input, "stdin") {
+        Ok(bytecode) => {
+            let mut thread = Thread::new(bytecode);
+            thread.exec().unwrap().unwrap()
+        }
+        Err(e) => panic!("{}", e.as_str()),
+    }
+}
+
+fn process_and_unwrap_runtime_error(compiler: &mut Compiler, input: &str) -> RuntimeErrorKind {
+    match compiler.compile(input, "stdin") {
+        Ok(bytecode) => {
+            let mut thread = Thread::new(bytecode);
+            thread.exec().unwrap_err().kind().clone()
+        }
+        Err(e) => panic!("{}", e.as_str()),
+    }
+}
+
+// Output sink that stays readable after it has been handed to a thread
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs the program with the given text as its input and returns everything it printed
+fn process_and_capture_output(compiler: &mut Compiler, input: &str, stdin: &str) -> String {
+    match compiler.compile(input, "stdin") {
+        Ok(bytecode) => {
+            let output = SharedOutput::default();
+            let mut thread = Thread::new(bytecode);
+            thread.set_output(Box::new(output.clone()));
+            thread.set_input(Box::new(std::io::Cursor::new(stdin.to_owned())));
+            thread.exec().unwrap();
+            String::from_utf8(output.0.take()).unwrap()
+        }
+        Err(e) => panic!("{}", e.as_str()),
+    }
+}
+
+// Runs the program in the debugger with the given commands and returns everything it printed
+fn debug_and_capture_output(compiler: &mut Compiler, input: &str, commands: &str) -> String {
+    let program = compiler.compile(input, "stdin").unwrap();
+    let output = SharedOutput::default();
+    let mut thread = Thread::new(program);
+    thread.set_output(Box::new(output.clone()));
+    let mut debugger = Debugger::new(thread);
+    debugger.set_output(Box::new(output.clone()));
+    debugger.set_input(Box::new(std::io::Cursor::new(commands.to_owned())));
+    debugger.run().unwrap();
+    String::from_utf8(output.0.take()).unwrap()
+}
+
+fn compile_and_unwrap_error(compiler: &mut Compiler, input: &str) -> String {
+    match compiler.compile(input, "stdin") {
+        Ok(_) => panic!("Expected a compile error for input:\n{input}"),
+        Err(e) => e,
+    }
+}
+
 #[test]
 fn operations_int() {
     let mut compiler = Compiler::new();
@@ -145,19 +220,19 @@ fn operations_string() {
     assert_eq!(output, Value::Bool(false));
 
     let output = process_and_unwrap_expression(&mut compiler, "\"abc\" + \"def\"");
-    assert_eq!(output, Value::Str(Box::new(String::from("abcdef"))));
+    assert_eq!(output, Value::Str(Rc::new(String::from("abcdef"))));
 
     let output = process_and_unwrap_expression(&mut compiler, "\"abc\" * 3");
-    assert_eq!(output, Value::Str(Box::new(String::from("abcabcabc"))));
+    assert_eq!(output, Value::Str(Rc::new(String::from("abcabcabc"))));
 
     let output = process_and_unwrap_expression(&mut compiler, "3 * \"abc\"");
-    assert_eq!(output, Value::Str(Box::new(String::from("abcabcabc"))));
+    assert_eq!(output, Value::Str(Rc::new(String::from("abcabcabc"))));
 
     let output = process_and_unwrap_expression(&mut compiler, "0 * \"abc\"");
-    assert_eq!(output, Value::Str(Box::new(String::from(""))));
+    assert_eq!(output, Value::Str(Rc::new(String::from(""))));
 
     let output = process_and_unwrap_expression(&mut compiler, "-3 * \"abc\"");
-    assert_eq!(output, Value::Str(Box::new(String::from(""))));
+    assert_eq!(output, Value::Str(Rc::new(String::from(""))));
 }
 
 #[test]
@@ -198,6 +273,65 @@ fn number_parsing() {
 
     let output = process_and_unwrap_expression(&mut compiler, "1_2_3.3_2_1");
     assert_eq!(output, Value::Float(123.321));
+
+    let output = process_and_unwrap_expression(&mut compiler, "0xFF");
+    assert_eq!(output, Value::Int(255));
+
+    let output = process_and_unwrap_expression(&mut compiler, "0xdead_beef");
+    assert_eq!(output, Value::Int(0xdead_beef));
+
+    let output = process_and_unwrap_expression(&mut compiler, "0b1010");
+    assert_eq!(output, Value::Int(10));
+
+    let output = process_and_unwrap_expression(&mut compiler, "0o755");
+    assert_eq!(output, Value::Int(493));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-0x10");
+    assert_eq!(output, Value::Int(-16));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1e-9");
+    assert_eq!(output, Value::Float(1e-9));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2.5E3");
+    assert_eq!(output, Value::Float(2500.0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1e3");
+    assert_eq!(output, Value::Float(1000.0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "9_223_372_036_854_775_807");
+    assert_eq!(output, Value::Int(i64::MAX));
+
+    // The minus sign belongs to the literal, so the minimum int is in range
+    let output = process_and_unwrap_program(&mut compiler, "return -9223372036854775808;");
+    assert_eq!(output, Value::Int(i64::MIN));
+
+    let output = process_and_unwrap_program(&mut compiler, "return -0x8000_0000_0000_0000;");
+    assert_eq!(output, Value::Int(i64::MIN));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-2 ** 2");
+    assert_eq!(output, Value::Int(-4));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-(-9223372036854775807)");
+    assert_eq!(output, Value::Int(i64::MAX));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return -9223372036854775809;");
+    assert!(error.ends_with("Error: Integer literal out of range"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 9223372036854775808;");
+    assert!(error.ends_with("Error: Integer literal out of range"));
+
+    // Only a minus sign directly in front of the number is part of the literal
+    let error = compile_and_unwrap_error(&mut compiler, "return -(9223372036854775808);");
+    assert!(error.ends_with("Error: Integer literal out of range"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return -[9223372036854775808];");
+    assert!(error.ends_with("Error: Integer literal out of range"));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-(5) * 2");
+    assert_eq!(output, Value::Int(-10));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 0x1_0000_0000_0000_0000;");
+    assert!(error.ends_with("Error: Integer literal out of range"));
 }
 
 #[test]
@@ -238,3 +372,1886 @@ fn expression() {
     let output = process_and_unwrap_expression(&mut compiler, "false or false or true");
     assert_eq!(output, Value::Bool(true));
 }
+
+#[test]
+fn if_statement() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let x = 5;
+let result = 0;
+if x > 3 {
+    result = 1;
+}
+return result;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(1));
+
+    let program = "
+let x = 2;
+let result = 0;
+if x > 3 {
+    result = 1;
+}
+return result;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(0));
+
+    let program = "
+let x = 2;
+let result = 0;
+if x > 3 {
+    result = 1;
+} else {
+    result = 2;
+}
+return result;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(2));
+
+    let program = "
+let result = \"\";
+let x = 0;
+while x < 4 {
+    x = x + 1;
+}
+if x == 1 {
+    result = \"one\";
+} else if x == 4 {
+    result = \"four\";
+} else if x == 5 {
+    result = \"five\";
+} else {
+    result = \"other\";
+}
+return result;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new(String::from("four"))));
+
+    let program = "
+let x = 7;
+let result = 0;
+if x == 1 {
+    result = 1;
+} else if x == 2 {
+    result = 2;
+}
+return result;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(0));
+
+    let program = "
+let x = 1;
+if x < 0 {
+} else {
+    x = 10;
+}
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(10));
+}
+
+#[test]
+fn if_statement_condition_type() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "if 1 { let x = 1; }");
+    assert!(error.ends_with("Error: Expected type 'bool', found type 'int'"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "if true { let x = 1; } else if \"a\" { let y = 2; }",
+    );
+    assert!(error.ends_with("Error: Expected type 'bool', found type 'string'"));
+}
+
+#[test]
+fn nested_blocks() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let total = 0;
+let i = 0;
+while i < 3 {
+    let j = 0;
+    while j < 4 {
+        total = total + 1;
+        j = j + 1;
+    }
+    i = i + 1;
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(12));
+
+    let program = "
+let evens = 0;
+let odds = 0;
+let i = 0;
+while i < 10 {
+    if i % 2 == 0 {
+        evens = evens + 1;
+    } else {
+        odds = odds + 1;
+    }
+    i = i + 1;
+}
+return evens * 100 + odds;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(505));
+
+    let program = "
+let total = 0;
+let i = 0;
+while i < 3 {
+    let j = 0;
+    while j < 3 {
+        let k = 0;
+        while k < 3 {
+            if i == j {
+                if j == k {
+                    total = total + 100;
+                }
+            } else if k == 0 {
+                total = total + 1;
+            }
+            k = k + 1;
+        }
+        j = j + 1;
+    }
+    i = i + 1;
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(306));
+
+    let program = "
+let x = 0;
+if true {
+    while x < 5 {
+        x = x + 1;
+    }
+}
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(5));
+}
+
+#[test]
+fn break_and_continue() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let x = 0;
+while true {
+    x = x + 1;
+    if x == 7 {
+        break;
+    }
+}
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(7));
+
+    let program = "
+let total = 0;
+let i = 0;
+while i < 10 {
+    i = i + 1;
+    if i % 2 == 0 {
+        continue;
+    }
+    total = total + i;
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(25));
+
+    let program = "
+let total = 0;
+let i = 0;
+while i < 5 {
+    let j = 0;
+    while true {
+        if j == i {
+            break;
+        }
+        j = j + 1;
+        total = total + 1;
+    }
+    i = i + 1;
+    if i == 4 {
+        break;
+    }
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(6));
+}
+
+#[test]
+fn break_and_continue_outside_loop() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1;\nbreak;");
+    assert!(error.ends_with("Error: 'break' can only be used inside a loop"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "if true {\n    continue;\n}");
+    assert!(error.contains("In line 2:\n\n 2|     continue;\n        ^^^^^^^^^\n"));
+    assert!(error.ends_with("Error: 'continue' can only be used inside a loop"));
+
+    // Loop control statements cannot leave a function
+    let error =
+        compile_and_unwrap_error(&mut compiler, "while true {\nlet f = fn() {\nbreak;\n};\n}");
+    assert!(error.contains("In line 3:"));
+    assert!(error.ends_with("Error: 'break' can only be used inside a loop"));
+}
+
+#[test]
+fn assignment_expression_operands() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let x = 1;
+let a = 1;
+let b = 2;
+x = (a + b) * (x + 3);
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(12));
+
+    let program = "
+fn first(a: int) -> int { return a * 2; }
+fn second(a: int) -> int { return a + 1; }
+let x = 0;
+x = first(3) + second(3);
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(10));
+}
+
+#[test]
+fn functions() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+fn add(a: int, b: int) -> int {
+    return a + b;
+}
+return add(2, 3) * add(1, 1);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(10));
+
+    let program = "
+fn fib(n: int) -> int {
+    if n < 2 {
+        return n;
+    }
+    return fib(n - 1) + fib(n - 2);
+}
+return fib(15);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(610));
+
+    let program = "
+fn describe(name: string, count: int) -> string {
+    if count == 1 {
+        return name;
+    } else {
+        return name + \"s\";
+    }
+}
+return describe(\"apple\", 1) + \" \" + describe(\"pear\", 2);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new(String::from("apple pears"))));
+
+    let program = "
+fn nothing(x: int) {
+    let y = x;
+    if y > 0 {
+        return;
+    }
+    y = 0;
+}
+let x = 5;
+nothing(x);
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(5));
+
+    let program = "
+fn count_down(n: int) -> int {
+    let steps = 0;
+    while true {
+        if n == 0 {
+            break;
+        }
+        n = n - 1;
+        steps = steps + 1;
+    }
+    return steps;
+}
+let i = 0;
+let total = 0;
+while i < 3 {
+    total = total + count_down(i);
+    i = i + 1;
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(3));
+}
+
+#[test]
+fn function_type_checking() {
+    let mut compiler = Compiler::new();
+
+    let program = "fn f(a: int) -> int { return a; }\nreturn f(\"a\");";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Expected type 'int', found type 'string'"));
+
+    let program = "fn f(a: int) -> int { return a; }\nreturn f(1, 2);";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Expected 1 arguments, found 2"));
+
+    let program = "fn f(a: int) -> bool { return a; }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Expected type 'bool', found type 'int'"));
+
+    let program = "fn f(a: int) -> int { if a > 0 { return a; } }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(
+        error.ends_with("Error: Function 'f' does not return a value of type 'int' on every path")
+    );
+
+    let program = "fn f() { }\nreturn f();";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Function 'f' does not return a value"));
+
+    let program = "fn f() { return 1; }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Function 'f' does not return a value"));
+
+    let program = "fn f() -> int { return; }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Expected a return value"));
+
+    let program = "fn f() { }\nfn f() { }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is already defined"));
+
+    let program = "let x = 1;\nreturn x(2);";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Value of type 'int' is not callable"));
+
+    let program = "let x = 1;\nfn f() -> int { return x; }";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier not found"));
+
+    let program = "while true {\n    fn f() { break; }\n}";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: 'break' can only be used inside a loop"));
+}
+
+#[test]
+fn closures() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let step = 2;
+let inc = fn(a: int) -> int { return a + step; };
+return inc(1) + inc(10);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(15));
+
+    // Captures are copied when the closure is created
+    let program = "
+let step = 2;
+let inc = fn(a: int) -> int { return a + step; };
+step = 100;
+return inc(1);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(3));
+
+    let program = "
+fn apply(f: fn(int) -> int, x: int) -> int {
+    return f(f(x));
+}
+fn double(x: int) -> int {
+    return x * 2;
+}
+let offset = 3;
+return apply(double, 5) + apply(fn(x: int) -> int { return x + offset; }, 0);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(26));
+
+    let program = "
+let prefix = \"a\";
+let outer = fn(middle: string) -> fn(string) -> string {
+    return fn(suffix: string) -> string { return prefix + middle + suffix; };
+};
+let inner = outer(\"b\");
+return inner(\"c\") + outer(\"x\")(\"y\");";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new(String::from("abcaxy"))));
+
+    let program = "
+let total = 0;
+let add = fn(a: int, b: int) -> int { return a + b; };
+let i = 0;
+while i < 4 {
+    total = add(total, i);
+    i = i + 1;
+}
+add = fn(a: int, b: int) -> int { return a * b; };
+return add(total, 2);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(12));
+
+    // The new closure captures the old value of the variable it is assigned to
+    let program = "
+let g = fn() -> int { return 1; };
+g = fn() -> int { return g() + 1; };
+return g();";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(2));
+}
+
+#[test]
+fn closure_type_checking() {
+    let mut compiler = Compiler::new();
+
+    let program = "let f = fn(a: int) -> int { return a; };\nreturn f(true);";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Expected type 'int', found type 'bool'"));
+
+    let program = "let f = fn(a: int) -> int { return a; };\nf = fn(a: int) { };";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with(
+        "Error: Cannot assign to a variable of type 'fn(int) -> int' a value of type 'fn(int)'"
+    ));
+
+    let program = "fn f(g: fn(int) -> int) -> int { return g(1); }\nreturn f(fn(a: string) -> int { return 1; });";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(
+        error.ends_with("Error: Expected type 'fn(int) -> int', found type 'fn(string) -> int'")
+    );
+
+    let program = "let f = fn() { };\nreturn f();";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Function 'f' does not return a value"));
+
+    let program = "let f = fn(a: int) -> int { return a; };\nreturn f + 1;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Invalid operation '+' for types 'fn(int) -> int' and 'int'"));
+
+    let program = "let f = fn(a: int) -> int { if a > 0 { return a; } };";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(
+        error.ends_with(
+            "Error: Function 'lambda' does not return a value of type 'int' on every path"
+        )
+    );
+
+    // Named functions do not capture, so neither can closures defined inside them
+    let program =
+        "let x = 1;\nfn f() -> int {\n    let g = fn() -> int { return x; };\n    return g();\n}";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier not found"));
+}
+
+#[test]
+fn compound_assignment() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 5;\nx += 3;\nreturn x;");
+    assert_eq!(output, Value::Int(8));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 5;\nx -= 7;\nreturn x;");
+    assert_eq!(output, Value::Int(-2));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 5;\nx *= 4;\nreturn x;");
+    assert_eq!(output, Value::Int(20));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 7;\nx /= 2;\nreturn x;");
+    assert_eq!(output, Value::Int(3));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 7;\nx %= 4;\nreturn x;");
+    assert_eq!(output, Value::Int(3));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 1.5;\nx += 2.0;\nreturn x;");
+    assert_eq!(output, Value::Float(3.5));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 1.5;\nx -= 2.0;\nreturn x;");
+    assert_eq!(output, Value::Float(-0.5));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 1.5;\nx *= 2.0;\nreturn x;");
+    assert_eq!(output, Value::Float(3.0));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 3.0;\nx /= 2.0;\nreturn x;");
+    assert_eq!(output, Value::Float(1.5));
+
+    let output = process_and_unwrap_program(&mut compiler, "let x = 7.5;\nx %= 2.0;\nreturn x;");
+    assert_eq!(output, Value::Float(1.5));
+
+    let output =
+        process_and_unwrap_program(&mut compiler, "let s = \"ab\";\ns += \"cd\";\nreturn s;");
+    assert_eq!(output, Value::Str(Rc::new(String::from("abcd"))));
+
+    let output = process_and_unwrap_program(&mut compiler, "let s = \"ab\";\ns *= 3;\nreturn s;");
+    assert_eq!(output, Value::Str(Rc::new(String::from("ababab"))));
+
+    // The right hand side is evaluated before the operation, including the variable itself
+    let program = "let x = 2;\nlet y = 3;\nx *= x + y;\nreturn x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(10));
+
+    let program = "let x = 0;\nlet i = 0;\nwhile i < 5 {\n    i += 1;\n    x += i;\n}\nreturn x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(15));
+}
+
+#[test]
+fn compound_assignment_type_checking() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1;\nx += 1.0;");
+    assert!(error.ends_with("Error: Invalid operation '+' for types 'int' and 'float'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1.0;\nx -= 1;");
+    assert!(error.ends_with("Error: Invalid operation '-' for types 'float' and 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = true;\nx += false;");
+    assert!(error.ends_with("Error: Invalid operation '+' for types 'bool' and 'bool'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 'a';\nx *= 2;");
+    assert!(error.ends_with("Error: Invalid operation '*' for types 'char' and 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let s = \"a\";\ns -= \"a\";");
+    assert!(error.ends_with("Error: Invalid operation '-' for types 'string' and 'string'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let s = \"a\";\ns /= 2;");
+    assert!(error.ends_with("Error: Invalid operation '/' for types 'string' and 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let s = \"a\";\ns %= 2;");
+    assert!(error.ends_with("Error: Invalid operation '%' for types 'string' and 'int'"));
+
+    // 'int * string' is a valid operation, but its result cannot be stored in an int
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 2;\nx *= \"a\";");
+    assert!(
+        error
+            .ends_with("Error: Cannot assign to a variable of type 'int' a value of type 'string'")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "x += 1;");
+    assert!(error.ends_with("Error: Identifier not found"));
+}
+
+#[test]
+fn block_scoping() {
+    let mut compiler = Compiler::new();
+
+    let program = "
+let x = 1;
+if true {
+    let x = \"inner\";
+    x += \"!\";
+}
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(1));
+
+    let program = "
+let x = 1;
+let x = x + 1;
+let x = \"shadowed\" * x;
+return x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new(String::from("shadowedshadowed")))
+    );
+
+    // Every iteration starts with a fresh variable
+    let program = "
+let total = 0;
+let i = 0;
+while i < 3 {
+    let step = 10;
+    step += i;
+    total += step;
+    i += 1;
+}
+return total;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(33));
+
+    // A variable initialized from another one must not share its register
+    let program = "let x = 1;\nlet y = x;\ny = 5;\nx;\nlet z = 2;\nreturn x + y + z;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(8));
+
+    // The registers of a scope are reused once it ends, so this does not run out of registers
+    let program = "if true { let a = 1; let b = 2; let c = a + b; }\n".repeat(200) + "return 0;";
+    let output = process_and_unwrap_program(&mut compiler, &program);
+    assert_eq!(output, Value::Int(0));
+}
+
+#[test]
+fn variable_out_of_scope() {
+    let mut compiler = Compiler::new();
+
+    let program = "if true {\n    let x = 1;\n}\nreturn x;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is not in scope anymore"));
+
+    let program = "while true {\n    let x = 1;\n    break;\n}\nx = 2;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is not in scope anymore"));
+
+    let program = "if true {\n    if true {\n        let x = 1;\n    }\n    x += 1;\n}";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is not in scope anymore"));
+
+    let program = "if true {\n    let x = 1;\n}\nreturn y;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier not found"));
+}
+
+#[test]
+fn type_annotations() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_program(&mut compiler, "let x: float = 0.5;\nreturn x;");
+    assert_eq!(output, Value::Float(0.5));
+
+    let program = "let f: fn(int) -> int = fn(a: int) -> int { return a * 2; };\nreturn f(4);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(8));
+
+    let program = "
+let s: string;
+let i = 3;
+if i > 2 {
+    s = \"big\";
+} else if i > 1 {
+    s = \"medium\";
+} else {
+    s = \"small\";
+}
+return s;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new(String::from("big"))));
+
+    // Branches which return do not have to assign the variable
+    let program = "
+fn sign(n: int) -> int {
+    let result: int;
+    if n < 0 {
+        result = -1;
+    } else if n == 0 {
+        return 0;
+    } else {
+        result = 1;
+    }
+    return result;
+}
+return sign(-5) + sign(0) * 10 + sign(7) * 100;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(99));
+
+    let program = "let x: int;\nx = 5;\nx += 1;\nreturn x;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(6));
+}
+
+#[test]
+fn type_annotation_errors() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: float = 0;");
+    assert!(error.ends_with("Error: Expected type 'float', found type 'int'"));
+    assert!(error.contains("let x: float = 0;\n         ^^^^^^^\n"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: int;\nx = \"a\";");
+    assert!(
+        error
+            .ends_with("Error: Cannot assign to a variable of type 'int' a value of type 'string'")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: int;\nreturn x;");
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: int;\nx += 1;");
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: int;\nx = x + 1;");
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let program = "let x: int;\nif true {\n    x = 1;\n}\nreturn x;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let program = "let x: int;\nif true {\n    x = 1;\n} else {\n    let y = 2;\n}\nreturn x;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let program = "let x: int;\nwhile true {\n    x = 1;\n    break;\n}\nreturn x;";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+
+    let program = "let x: int;\nlet f = fn() -> int { return x; };";
+    let error = compile_and_unwrap_error(&mut compiler, program);
+    assert!(error.ends_with("Error: Identifier is used before being assigned a value"));
+}
+
+#[test]
+fn casts() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "3 as float / 2.0");
+    assert_eq!(output, Value::Float(1.5));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-2.7 as int");
+    assert_eq!(output, Value::Int(-2));
+
+    let output = process_and_unwrap_expression(&mut compiler, "'a' as int");
+    assert_eq!(output, Value::Int(97));
+
+    let output = process_and_unwrap_expression(&mut compiler, "98 as char");
+    assert_eq!(output, Value::Char('b'));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1114111 as char");
+    assert_eq!(output, Value::Char('\u{10FFFF}'));
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "return -1 as char;");
+    assert_eq!(error, RuntimeErrorKind::InvalidCodePoint(-1));
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "return 1114112 as char;");
+    assert_eq!(error, RuntimeErrorKind::InvalidCodePoint(0x110000));
+
+    // Surrogates are not valid chars either
+    let program = compiler
+        .compile(
+            "let x = 55296;
+return x as char;",
+            "test",
+        )
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    assert!(thread.source_map().format_error(&error).ends_with(
+        " 2| return x as char;\n           ^^^^^^^^^\n\nError: 55296 is not a valid char code point"
+    ));
+
+    let output = process_and_unwrap_expression(
+        &mut compiler,
+        "12 as string + 0.5 as string + true as string + 'c' as string",
+    );
+    assert_eq!(output, Value::Str(Rc::new(String::from("120.5truec"))));
+
+    let output = process_and_unwrap_expression(&mut compiler, "(1 + 2) as float as int");
+    assert_eq!(output, Value::Int(3));
+
+    let program = "let x = 7;\nlet y = x as int;\ny += 1;\nlet z = 0.0;\nz = x as float;\nreturn z + y as float;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Float(15.0));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return true as int;");
+    assert!(error.ends_with("Error: Cannot cast a value of type 'bool' to type 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return \"1\" as int;");
+    assert!(error.ends_with("Error: Cannot cast a value of type 'string' to type 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 1.5 as char;");
+    assert!(error.ends_with("Error: Cannot cast a value of type 'float' to type 'char'"));
+}
+
+#[test]
+fn int_promotion() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 1 + 2.5;");
+    assert!(error.ends_with("Error: Invalid operation '+' for types 'int' and 'float'"));
+
+    compiler.set_int_promotion(true);
+
+    let output = process_and_unwrap_expression(&mut compiler, "1 + 2.5");
+    assert_eq!(output, Value::Float(3.5));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2.5 * 2");
+    assert_eq!(output, Value::Float(5.0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "3 > 2.5");
+    assert_eq!(output, Value::Bool(true));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2 == 2.0");
+    assert_eq!(output, Value::Bool(true));
+
+    let program = "let i = 3;\nlet f = 0.5;\nf += i;\nreturn f / i;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Float(3.5 / 3.0));
+
+    // The variable itself keeps its type
+    let program = "let i = 3;\nlet f = i + 0.5;\ni += 1;\nreturn i;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(4));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let i = 1;\ni += 0.5;");
+    assert!(
+        error.ends_with("Error: Cannot assign to a variable of type 'int' a value of type 'float'")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 1 and 2.0;");
+    assert!(error.ends_with("Error: Invalid operation 'and' for types 'int' and 'float'"));
+}
+
+#[test]
+fn lists() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "[1, 2, 3][1]");
+    assert_eq!(output, Value::Int(2));
+
+    let output = process_and_unwrap_expression(&mut compiler, "len([1.5, 2.5,])");
+    assert_eq!(output, Value::Int(2));
+
+    let program = "let xs = [[1, 2], [3, 4]];\nreturn xs[1][0] + xs[0][1];";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(5));
+
+    let program = "let xs = [1, 2, 3];\nxs[0] = 10;\nxs[2] *= 5;\nreturn xs[0] + xs[2];";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(25));
+
+    let program = "let xs = [[1], [2]];\nxs[1][0] += 40;\nreturn xs[1][0];";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(42));
+
+    let program = r#"
+let xs: [int] = [];
+let i = 0;
+while i < 5 {
+    push(xs, i * i);
+    i += 1;
+}
+let last = pop(xs);
+return last + len(xs);
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(20));
+
+    // Lists are shared by reference
+    let program = "let xs = [1];\nlet ys = xs;\npush(ys, 2);\nreturn len(xs);";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(2));
+
+    let program = r#"
+fn sum(xs: [int]) -> int {
+    let total = 0;
+    let i = 0;
+    while i < len(xs) {
+        total += xs[i];
+        i += 1;
+    }
+    return total;
+}
+return sum([1, 2, 3]) + sum([]);
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(6));
+}
+
+#[test]
+fn list_errors() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let xs = [1, true];");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'bool'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let xs = [];");
+    assert!(error.ends_with("Error: Cannot infer the element type of an empty list"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let xs = [1];\nreturn xs[true];");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'bool'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1;\nreturn x[0];");
+    assert!(error.ends_with("Error: Value of type 'int' cannot be indexed"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let xs = [1];\npush(xs, 1.5);");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'float'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return len(5);");
+    assert!(error.ends_with("Error: Expected a list or map, found type 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return pop(5);");
+    assert!(error.ends_with("Error: Expected a list, found type 'int'"));
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let xs = [1, 2];\nreturn xs[2];");
+    assert_eq!(error, RuntimeErrorKind::IndexOutOfBounds(2, 2));
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let xs = [1];\nxs[-1] = 0;");
+    assert_eq!(error, RuntimeErrorKind::IndexOutOfBounds(-1, 1));
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let xs: [int] = [];\npop(xs);");
+    assert_eq!(error, RuntimeErrorKind::PopFromEmptyList);
+}
+
+#[test]
+fn structs() {
+    let mut compiler = Compiler::new();
+
+    let program = r#"
+struct Point { x: float, y: float }
+let p = Point { y: 2.5, x: 1.0 };
+return p.x + p.y;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Float(3.5));
+
+    let program = r#"
+struct Point { x: int, y: int, }
+let p = Point { x: 1, y: 2 };
+p.x = 10;
+p.y *= 3;
+return p.x + p.y;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(16));
+
+    // Nested structs, lists of structs and structs as function arguments
+    let program = r#"
+struct Point { x: int, y: int }
+struct Line { from: Point, to: Point }
+fn length_squared(line: Line) -> int {
+    let dx = line.to.x - line.from.x;
+    let dy = line.to.y - line.from.y;
+    return dx * dx + dy * dy;
+}
+let lines = [Line { from: Point { x: 0, y: 0 }, to: Point { x: 3, y: 4 } }];
+lines[0].to.x += 3;
+return length_squared(lines[0]);
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(52));
+
+    // Structs are shared by reference
+    let program = r#"
+struct Counter { count: int }
+fn increment(counter: Counter) {
+    counter.count += 1;
+}
+let c = Counter { count: 0 };
+increment(c);
+increment(c);
+return c.count;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(2));
+
+    // Fields can be read from any expression, and a struct can refer to itself through a list
+    let program = r#"
+struct Node { value: int, children: [Node] }
+fn make(value: int) -> Node {
+    return Node { value: value, children: [] };
+}
+let root = make(1);
+push(root.children, make(2));
+return root.children[0].value + make(40).value;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(42));
+
+    // A struct which contains itself is printed without recursing forever
+    let program = r#"
+struct Node { next: [Node] }
+let a = Node { next: [] };
+push(a.next, a);
+println(a);
+let xs = [1];
+let ys = [xs, xs];
+println(ys);
+"#;
+    let output = process_and_capture_output(&mut compiler, program, "");
+    assert_eq!(output, "{[<cycle>]}\n[[1], [1]]\n");
+}
+
+#[test]
+fn struct_errors() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let p: Point = 1;");
+    assert!(error.ends_with("Error: Unknown type 'Point'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let p = Point { x: 1 };");
+    assert!(error.ends_with("Error: Unknown type 'Point'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "struct P { x: int, x: float }");
+    assert!(error.ends_with("Error: Field 'x' is specified more than once"));
+
+    let fields: Vec<String> = (0..256).map(|idx| format!("f{idx}: int")).collect();
+    let source = format!("struct Wide {{ {} }}", fields.join(", "));
+    let error = compile_and_unwrap_error(&mut compiler, &source);
+    assert!(error.contains("1| struct Wide {"));
+    assert!(error.ends_with("Error: Struct 'Wide' has more than 255 fields"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "struct P { x: Q }");
+    assert!(error.ends_with("Error: Unknown type 'Q'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "struct P { x: int }\nstruct P { y: int }");
+    assert!(error.ends_with("Error: Identifier is already defined"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "struct P { x: int, y: int }\nlet p = P { x: 1 };",
+    );
+    assert!(error.ends_with("Error: Missing field 'y' of struct 'P'"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "struct P { x: int }\nlet p = P { x: 1, x: 2 };",
+    );
+    assert!(error.ends_with("Error: Field 'x' is specified more than once"));
+
+    let error =
+        compile_and_unwrap_error(&mut compiler, "struct P { x: int }\nlet p = P { x: 1.5 };");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'float'"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "struct P { x: int }\nlet p = P { x: 1 };\nreturn p.z;",
+    );
+    assert!(error.ends_with("Error: Struct 'P' has no field 'z'"));
+    assert!(error.contains("return p.z;\n             ^\n"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "struct P { x: int }\nlet p = P { x: 1 };\np.x = true;",
+    );
+    assert!(
+        error.ends_with("Error: Cannot assign to a variable of type 'int' a value of type 'bool'")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1;\nreturn x.y;");
+    assert!(error.ends_with("Error: Value of type 'int' has no fields"));
+
+    let error = compile_and_unwrap_error(
+        &mut compiler,
+        "struct P { x: int }\nlet p = P { x: 1 };\nreturn p + p;",
+    );
+    assert!(error.ends_with("Error: Invalid operation '+' for types 'P' and 'P'"));
+}
+
+#[test]
+fn maps() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "{\"a\": 1, \"b\": 2}[\"b\"]");
+    assert_eq!(output, Value::Int(2));
+
+    let program = r#"
+let m = {1: "one", 2: "two",};
+m[3] = "three";
+m[1] = "uno";
+return m[1] + m[3] + len(m) as string;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new("unothree3".to_owned())));
+
+    let program = r#"
+let counts: {string: int} = {};
+let words = ["b", "a", "b", "c", "b"];
+let i = 0;
+while i < len(words) {
+    if contains(counts, words[i]) {
+        counts[words[i]] += 1;
+    } else {
+        counts[words[i]] = 1;
+    }
+    i += 1;
+}
+return counts["b"] * 10 + remove(counts, "a") + len(counts);
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Int(33));
+
+    // Keys are iterated in ascending order, independent of the insertion order
+    let program = r#"
+let m = {"pear": 1, "apple": 2};
+m["fig"] = 3;
+let result = "";
+let ks = keys(m);
+let i = 0;
+while i < len(ks) {
+    result += ks[i] + "=" + m[ks[i]] as string + ";";
+    i += 1;
+}
+return result;
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new("apple=2;fig=3;pear=1;".to_owned()))
+    );
+
+    let program = "let m = {10: [1], 5: [2, 3]};\npush(m[10], 4);\nreturn m;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output.to_string(), "{5: [2, 3], 10: [1, 4]}");
+}
+
+#[test]
+fn map_errors() {
+    let mut compiler = Compiler::new();
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {};");
+    assert!(error.ends_with("Error: Cannot infer the key and value types of an empty map"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {1.5: 1};");
+    assert!(error.ends_with("Error: Type 'float' cannot be used as a map key"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m: {bool: int} = {};");
+    assert!(error.ends_with("Error: Type 'bool' cannot be used as a map key"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {\"a\": 1, \"b\": true};");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'bool'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {\"a\": 1};\nreturn m[1];");
+    assert!(error.ends_with("Error: Expected type 'string', found type 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {\"a\": 1};\nm[\"b\"] = 0.5;");
+    assert!(
+        error.ends_with("Error: Cannot assign to a variable of type 'int' a value of type 'float'")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "return keys([1]);");
+    assert!(error.ends_with("Error: Expected a map, found type '[int]'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let m = {1: 1};\nreturn m == m;");
+    assert!(
+        error.ends_with("Error: Invalid operation '==' for types '{int: int}' and '{int: int}'")
+    );
+
+    let program = "let m = {\"a\": 1};\nreturn m[\"b\"];";
+    let error = process_and_unwrap_runtime_error(&mut compiler, program);
+    assert_eq!(error, RuntimeErrorKind::KeyNotFound("b".to_owned()));
+
+    let program = "let m = {1: 1};\nremove(m, 1);\nremove(m, 1);";
+    let error = process_and_unwrap_runtime_error(&mut compiler, program);
+    assert_eq!(error, RuntimeErrorKind::KeyNotFound("1".to_owned()));
+}
+
+#[test]
+fn escape_sequences() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, r#""a\"b\\c""#);
+    assert_eq!(output, Value::Str(Rc::new("a\"b\\c".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, r#""tab\tnew\nline\r\0'\'""#);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new("tab\tnew\nline\r\0''".to_owned()))
+    );
+
+    let output = process_and_unwrap_expression(&mut compiler, r#""\u{48}i \u{1F600}""#);
+    assert_eq!(output, Value::Str(Rc::new("Hi \u{1F600}".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, r"'\n'");
+    assert_eq!(output, Value::Char('\n'));
+
+    let output = process_and_unwrap_expression(&mut compiler, r"'\''");
+    assert_eq!(output, Value::Char('\''));
+
+    let output = process_and_unwrap_expression(&mut compiler, r"'\u{e9}'");
+    assert_eq!(output, Value::Char('é'));
+
+    let output = process_and_unwrap_expression(&mut compiler, r#"len(["\\", "\""])"#);
+    assert_eq!(output, Value::Int(2));
+
+    let error = compile_and_unwrap_error(&mut compiler, r#"let s = "ab\qc";"#);
+    assert!(error.ends_with(
+        "let s = \"ab\\qc\";\n               ^^\n\nError: Invalid escape sequence '\\q'"
+    ));
+
+    let error = compile_and_unwrap_error(&mut compiler, r#"let s = "\u{110000}";"#);
+    assert!(error.ends_with("Error: Invalid escape sequence '\\u{110000}'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, r#"let s = "\u{zz}";"#);
+    assert!(error.ends_with("Error: Invalid escape sequence '\\u{zz}'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, r"let c = '\x';");
+    assert!(error.ends_with("Error: Invalid escape sequence '\\x'"));
+}
+
+#[test]
+fn string_interpolation() {
+    let mut compiler = Compiler::new();
+
+    let program = r#"
+let x = 3;
+let a = 1.5;
+let b = 2.5;
+return "x = {x}, total = { a + b }, ok = {x > 2}";
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new("x = 3, total = 4, ok = true".to_owned()))
+    );
+
+    let program = r#"
+let name = "world";
+let xs = [1, 2];
+fn shout(s: string) -> string {
+    return s + "!";
+}
+return "{shout("hello {name}")} {xs} {'c'}{{}}";
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new("hello world! [1, 2] c{}".to_owned()))
+    );
+
+    // The variable used in the string is overwritten by the result
+    let program = "let n = 2;\nlet s = \"{n}\";\ns = \"{s}{s}\";\nreturn s;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Str(Rc::new("22".to_owned())));
+
+    let output =
+        process_and_unwrap_program(&mut compiler, r#"let x = 1; return "{{x}} {{ {x} }}";"#);
+    assert_eq!(output, Value::Str(Rc::new("{x} { 1 }".to_owned())));
+
+    // An embedded literal is converted like any other value, even when it is the only part
+    let output = process_and_unwrap_program(&mut compiler, "let s: string = \"{1}\"; return s;");
+    assert_eq!(output, Value::Str(Rc::new("1".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, "\"{1}\" + \"x\"");
+    assert_eq!(output, Value::Str(Rc::new("1x".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, "\"{true} x\"");
+    assert_eq!(output, Value::Str(Rc::new("true x".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, "\"{1} apples\"");
+    assert_eq!(output, Value::Str(Rc::new("1 apples".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, "\"{\"a\"}{2.5}b\"");
+    assert_eq!(output, Value::Str(Rc::new("a2.5b".to_owned())));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let s = \"a {1 + true} b\";");
+    assert!(error.ends_with(
+        "let s = \"a {1 + true} b\";\n                  ^\n\nError: Invalid operation '+' for types 'int' and 'bool'"
+    ));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let s = \"a {y}\";");
+    assert!(
+        error.ends_with("let s = \"a {y}\";\n                ^\n\nError: Identifier not found")
+    );
+}
+
+#[test]
+fn short_circuit_evaluation() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "true and false or 1 < 2");
+    assert_eq!(output, Value::Bool(true));
+
+    // The right operand would fail with an out of bounds index if it was evaluated
+    let program = "let xs = [1];\nreturn len(xs) > 5 and xs[5] == 1;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Bool(false));
+
+    let program = "let xs = [1];\nreturn len(xs) == 1 or xs[5] == 1;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Bool(true));
+
+    // Every call records itself, so the log shows which operands were evaluated
+    let program = r#"
+let log: [string] = [];
+let check = fn(name: string, result: bool) -> bool {
+    push(log, name);
+    return result;
+};
+let a = check("a", false) and check("b", true);
+let b = check("c", true) or check("d", true);
+let c = check("e", true) and check("f", false);
+let d = check("g", false) or check("h", true);
+return "{log} {a} {b} {c} {d}";
+"#;
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(
+        output,
+        Value::Str(Rc::new(
+            "[a, c, e, f, g, h] false true false true".to_owned()
+        ))
+    );
+
+    // The operands may read the variable which receives the result
+    let program = "let x = true;\nlet y = false;\nx = y or x;\ny = x and y;\nreturn x and not y;";
+    let output = process_and_unwrap_program(&mut compiler, program);
+    assert_eq!(output, Value::Bool(true));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return true and 1;");
+    assert!(error.ends_with("Error: Invalid operation 'and' for types 'bool' and 'int'"));
+}
+
+#[test]
+fn bitwise_operators() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "12 & 10");
+    assert_eq!(output, Value::Int(8));
+
+    let output = process_and_unwrap_expression(&mut compiler, "12 | 10");
+    assert_eq!(output, Value::Int(14));
+
+    let output = process_and_unwrap_expression(&mut compiler, "12 ^ 10");
+    assert_eq!(output, Value::Int(6));
+
+    let output = process_and_unwrap_expression(&mut compiler, "~5");
+    assert_eq!(output, Value::Int(-6));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1 << 4");
+    assert_eq!(output, Value::Int(16));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-16 >> 2");
+    assert_eq!(output, Value::Int(-4));
+
+    // Precedence: shifts bind weaker than '+', then '&', '^', '|' and finally comparisons
+    let output = process_and_unwrap_expression(&mut compiler, "1 << 2 + 1");
+    assert_eq!(output, Value::Int(8));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1 | 6 ^ 3 & 5");
+    assert_eq!(output, Value::Int(7));
+
+    let output = process_and_unwrap_expression(&mut compiler, "6 & 3 == 2");
+    assert_eq!(output, Value::Bool(true));
+
+    let output = process_and_unwrap_expression(&mut compiler, "~1 << 1 < 0");
+    assert_eq!(output, Value::Bool(true));
+
+    // Out of range shift amounts shift out every bit
+    let output = process_and_unwrap_expression(&mut compiler, "1 << 64");
+    assert_eq!(output, Value::Int(0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "5 << -1");
+    assert_eq!(output, Value::Int(0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "5 >> 100");
+    assert_eq!(output, Value::Int(0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-5 >> 100");
+    assert_eq!(output, Value::Int(-1));
+
+    let output = process_and_unwrap_expression(&mut compiler, "1 << 63");
+    assert_eq!(output, Value::Int(i64::MIN));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 1.5 & 1;");
+    assert!(error.ends_with("Error: Invalid operation '&' for types 'float' and 'int'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return ~true;");
+    assert!(error.ends_with("Error: Invalid operation '~' for type 'bool'"));
+
+    compiler.set_int_promotion(true);
+    let error = compile_and_unwrap_error(&mut compiler, "return 1 << 2.0;");
+    assert!(error.ends_with("Error: Invalid operation '<<' for types 'int' and 'float'"));
+}
+
+#[test]
+fn power_and_floor_division() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_unwrap_expression(&mut compiler, "2 ** 10");
+    assert_eq!(output, Value::Int(1024));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2.0 ** 0.5");
+    assert_eq!(output, Value::Float(2.0f64.sqrt()));
+
+    // '**' binds tighter than unary minus and groups to the right
+    let output = process_and_unwrap_expression(&mut compiler, "-2 ** 2");
+    assert_eq!(output, Value::Int(-4));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2 ** 3 ** 2");
+    assert_eq!(output, Value::Int(512));
+
+    let output = process_and_unwrap_expression(&mut compiler, "2.0 ** -1.0");
+    assert_eq!(output, Value::Float(0.5));
+
+    let output = process_and_unwrap_expression(&mut compiler, "3 * 2 ** 2");
+    assert_eq!(output, Value::Int(12));
+
+    let output = process_and_unwrap_expression(&mut compiler, "(-1) ** 9000000000");
+    assert_eq!(output, Value::Int(1));
+
+    let output = process_and_unwrap_expression(&mut compiler, "7 // 2");
+    assert_eq!(output, Value::Int(3));
+
+    // Floor division rounds towards negative infinity instead of towards zero
+    let output = process_and_unwrap_expression(&mut compiler, "-7 // 2");
+    assert_eq!(output, Value::Int(-4));
+
+    let output = process_and_unwrap_expression(&mut compiler, "7 // -2");
+    assert_eq!(output, Value::Int(-4));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-7 // -2");
+    assert_eq!(output, Value::Int(3));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-6 // 2");
+    assert_eq!(output, Value::Int(-3));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-7.5 // 2.0");
+    assert_eq!(output, Value::Float(-4.0));
+
+    // The remainder takes the sign of the divisor, so that (a // b) * b + a % b == a
+    let output = process_and_unwrap_expression(&mut compiler, "-7 % 2");
+    assert_eq!(output, Value::Int(1));
+
+    let output = process_and_unwrap_expression(&mut compiler, "7 % -2");
+    assert_eq!(output, Value::Int(-1));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-7 % -2");
+    assert_eq!(output, Value::Int(-1));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-6 % 2");
+    assert_eq!(output, Value::Int(0));
+
+    let output = process_and_unwrap_expression(&mut compiler, "(-7 // 3) * 3 + -7 % 3");
+    assert_eq!(output, Value::Int(-7));
+
+    let output = process_and_unwrap_expression(&mut compiler, "(7 // -3) * -3 + 7 % -3");
+    assert_eq!(output, Value::Int(7));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-7.5 % 2.0");
+    assert_eq!(output, Value::Float(0.5));
+
+    let output = process_and_unwrap_expression(&mut compiler, "7.5 % -2.0");
+    assert_eq!(output, Value::Float(-0.5));
+
+    // Line comments start with '#', so '//' is free to be an operator
+    let output = process_and_unwrap_program(&mut compiler, "# comment\nreturn 9 // 2; # 4");
+    assert_eq!(output, Value::Int(4));
+
+    // Scripts written for the old '//' comments get a hint instead of a confusing parse error
+    let error = compile_and_unwrap_error(&mut compiler, "let x = 1;\n// old comment\nreturn x;");
+    assert!(error.contains(" 2| // old comment\n    ^^^^^^^^^^^^^^"));
+    assert!(
+        error.ends_with("Error: Line comments start with '#', '//' is the floor division operator")
+    );
+
+    let error = compile_and_unwrap_error(&mut compiler, "while true {\nbreak; // done\n}");
+    assert!(
+        error.ends_with("Error: Line comments start with '#', '//' is the floor division operator")
+    );
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "return 2 ** 63;");
+    assert_eq!(error, RuntimeErrorKind::IntegerOverflow);
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "return 2 ** -1;");
+    assert_eq!(error, RuntimeErrorKind::NegativeExponent(-1));
+    assert_eq!(
+        error.to_string(),
+        "Cannot raise an int to the negative power -1"
+    );
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let x = 0;\nreturn 1 // x;");
+    assert_eq!(error, RuntimeErrorKind::DivisionByZero);
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let x = 0;\nreturn 1 / x;");
+    assert_eq!(error, RuntimeErrorKind::DivisionByZero);
+
+    let error = compile_and_unwrap_error(&mut compiler, "return 2 ** 1.0;");
+    assert!(error.ends_with("Error: Invalid operation '**' for types 'int' and 'float'"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "return true // 1;");
+    assert!(error.ends_with("Error: Invalid operation '//' for types 'bool' and 'int'"));
+}
+
+#[test]
+fn input_output() {
+    let mut compiler = Compiler::new();
+
+    let output = process_and_capture_output(&mut compiler, "println(\"Hello\");", "");
+    assert_eq!(output, "Hello\n");
+
+    let output = process_and_capture_output(
+        &mut compiler,
+        "print(1); print(' '); print(2.5); println(true);",
+        "",
+    );
+    assert_eq!(output, "1 2.5true\n");
+
+    let output = process_and_capture_output(
+        &mut compiler,
+        "struct P { x: int, y: int }\nprintln([1, 2]); println({\"a\": 1}); println(P { x: 1, y: 2 });",
+        "",
+    );
+    assert_eq!(output, "[1, 2]\n{a: 1}\n{1, 2}\n");
+
+    // Line endings are stripped, and reading past the end gives empty strings
+    let output = process_and_capture_output(
+        &mut compiler,
+        "let name = read_line();\nlet greeting = read_line();\nprintln(\"{greeting}, {name}!\");\nprintln(read_line() == \"\");",
+        "World\r\nHello\n",
+    );
+    assert_eq!(output, "Hello, World!\ntrue\n");
+
+    let output = process_and_capture_output(&mut compiler, "println(0);\nreturn 1;", "");
+    assert_eq!(output, "0\n");
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x = println(1);");
+    assert!(error.ends_with("Error: Function 'println' does not return a value"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "print(1, 2);");
+    assert!(error.ends_with("Error: Expected 1 arguments, found 2"));
+
+    let error = compile_and_unwrap_error(&mut compiler, "let x: int = read_line();");
+    assert!(error.ends_with("Error: Expected type 'int', found type 'string'"));
+}
+
+#[test]
+fn constant_pool() {
+    let mut compiler = Compiler::new();
+
+    let program = compiler
+        .compile(
+            "let a = \"x\";\nlet b = \"x\";\nlet c = 2.5;\nlet d = 2.5;\nlet e = 100000;\nlet f = 7;\nlet g = 'x';",
+            "stdin",
+        )
+        .unwrap();
+    let (instructions, constants, _) = program.into_parts();
+    assert_eq!(
+        constants,
+        vec![
+            Value::Str(Rc::new("x".to_owned())),
+            Value::Float(2.5),
+            Value::Int(100000),
+            Value::Char('x'),
+        ]
+    );
+    assert!(
+        instructions
+            .iter()
+            .any(|op| matches!(op, Opcode::LoadNum(_, 7)))
+    );
+
+    // Appending to a string loaded from the pool must not change the constant
+    let output = process_and_unwrap_program(
+        &mut compiler,
+        "let out = \"\";\nlet i = 0;\nwhile i < 3 {\nout = out + \"x{i}\";\ni += 1;\n}\nreturn out;",
+    );
+    assert_eq!(output, Value::Str(Rc::new("x0x1x2".to_owned())));
+
+    let output = process_and_unwrap_expression(&mut compiler, "-32768 + 32767 * 2");
+    assert_eq!(output, Value::Int(32766));
+
+    let output = process_and_unwrap_expression(&mut compiler, "40000 - 1");
+    assert_eq!(output, Value::Int(39999));
+
+    // LoadConst addresses the pool with 16 bits
+    let mut source = String::from("let x = 0.5;\n");
+    for idx in 1..=65536 {
+        source.push_str(&format!("x = {idx}.5;\n"));
+    }
+    let error = compile_and_unwrap_error(&mut compiler, &source);
+    assert!(error.contains("65537| x = 65536.5;\n"));
+    assert!(error.ends_with("Error: Too many distinct constants, at most 65536 are supported"));
+}
+
+#[test]
+fn runtime_errors() {
+    let mut compiler = Compiler::new();
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "return 9223372036854775807 + 1;");
+    assert_eq!(error, RuntimeErrorKind::IntegerOverflow);
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let x = 1 << 63;\nreturn -x;");
+    assert_eq!(error, RuntimeErrorKind::IntegerOverflow);
+
+    let error = process_and_unwrap_runtime_error(&mut compiler, "let x = 0;\nreturn 1 % x;");
+    assert_eq!(error, RuntimeErrorKind::DivisionByZero);
+
+    // Runaway recursion is stopped before it exhausts the memory
+    let error = process_and_unwrap_runtime_error(
+        &mut compiler,
+        "fn f() -> int {\nreturn f();\n}\nreturn f();",
+    );
+    assert_eq!(error, RuntimeErrorKind::StackOverflow);
+
+    // Deep but finite recursion still works
+    let output = process_and_unwrap_program(
+        &mut compiler,
+        "fn sum(n: int) -> int {\nif n == 0 {\nreturn 0;\n}\nreturn n + sum(n - 1);\n}\nreturn sum(5000);",
+    );
+    assert_eq!(output, Value::Int(12502500));
+
+    let error =
+        process_and_unwrap_runtime_error(&mut compiler, "return \"ab\" * 9223372036854775807;");
+    assert_eq!(error, RuntimeErrorKind::IntegerOverflow);
+
+    // The error refers to the instruction which failed
+    let program = compiler
+        .compile("let x = 0;\nlet y = 1;\nreturn y / x;", "stdin")
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::DivisionByZero);
+    assert_eq!(error.program_counter(), 2);
+
+    // Malformed bytecode is reported instead of panicking
+    let mut thread = Thread::new(Program::new(
+        vec![Opcode::Error],
+        Vec::new(),
+        SourceMap::default(),
+    ));
+    let error = thread.exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::InvalidBytecode);
+    assert_eq!(error.program_counter(), 0);
+
+    let program = Program::new(
+        vec![Opcode::LoadBool(0, true), Opcode::NegInt(0, 1)],
+        Vec::new(),
+        SourceMap::default(),
+    );
+    let error = Thread::new(program).exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::TypeMismatch("int", "bool"));
+    assert_eq!(error.program_counter(), 1);
+
+    let program = Program::new(
+        vec![Opcode::LoadConst(0, 3)],
+        Vec::new(),
+        SourceMap::default(),
+    );
+    let error = Thread::new(program).exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::InvalidBytecode);
+
+    let program = Program::new(vec![Opcode::Return(0)], Vec::new(), SourceMap::default());
+    let error = Thread::new(program).exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::InvalidBytecode);
+
+    // Programs without a return statement finish without a value
+    let program = compiler.compile("let x = 1;", "stdin").unwrap();
+    assert_eq!(Thread::new(program).exec(), Ok(None));
+}
+
+#[test]
+fn runtime_error_locations() {
+    let mut compiler = Compiler::new();
+
+    let program = compiler
+        .compile("let x = 0;\nlet y = 10;\nreturn y / x;", "test")
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    let expected = "In file: test
+In line 3:
+
+ 3| return y / x;
+           ^^^^^
+
+Error: Division by zero";
+    assert_eq!(thread.source_map().format_error(&error), expected);
+
+    // Errors inside a function point at the failing expression, not at the call
+    let program = compiler
+        .compile(
+            "fn get(xs: [int], i: int) -> int {\n    return xs[i];\n}\nreturn get([1, 2], 5);",
+            "test",
+        )
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    let formatted = thread.source_map().format_error(&error);
+    assert!(formatted.contains("In line 2:"));
+    assert!(formatted.ends_with(
+        " 2|     return xs[i];\n               ^^^^^\n\nError: Index 5 is out of bounds for a list of length 2"
+    ));
+
+    let program = compiler
+        .compile(
+            "let total = 0;\nwhile true {\n    total += 1 << 62;\n}",
+            "test",
+        )
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    assert!(thread.source_map().format_error(&error).ends_with(
+        " 3|     total += 1 << 62;\n        ^^^^^^^^^^^^^^^^^\n\nError: Integer overflow"
+    ));
+
+    // Hand written bytecode has no source locations
+    let program = Program::new(vec![Opcode::Error], Vec::new(), SourceMap::default());
+    let mut thread = Thread::new(program);
+    let error = thread.exec().unwrap_err();
+    assert_eq!(
+        thread.source_map().format_error(&error),
+        "Runtime error at instruction 0: Invalid bytecode"
+    );
+}
+
+#[test]
+fn fuel() {
+    let mut compiler = Compiler::new();
+
+    let program = compiler.compile("while true {}", "stdin").unwrap();
+    let mut thread = Thread::new(program);
+    thread.set_fuel(Some(1000));
+    let error = thread.exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::OutOfFuel);
+    assert_eq!(thread.fuel(), Some(0));
+
+    // Resuming continues with the state from before, instead of starting over
+    let program = compiler
+        .compile(
+            "let i = 0;\nwhile i < 100 {\ni += 1;\n}\nreturn i;",
+            "stdin",
+        )
+        .unwrap();
+    let mut thread = Thread::new(program);
+    thread.set_fuel(Some(50));
+    let mut result = thread.exec();
+    let mut refuels = 0;
+    while let Err(error) = &result {
+        assert_eq!(error.kind(), &RuntimeErrorKind::OutOfFuel);
+        refuels += 1;
+        thread.set_fuel(Some(50));
+        result = thread.resume();
+    }
+    assert_eq!(result, Ok(Some(Value::Int(100))));
+    assert!(refuels > 1);
+
+    // Exactly enough fuel finishes the program
+    let program = compiler.compile("return 1;", "stdin").unwrap();
+    let instruction_count = program.to_string().lines().count() as u64;
+    let mut thread = Thread::new(program);
+    thread.set_fuel(Some(instruction_count));
+    assert_eq!(thread.exec(), Ok(Some(Value::Int(1))));
+    assert_eq!(thread.fuel(), Some(0));
+
+    let program = compiler.compile("return 1;", "stdin").unwrap();
+    let mut thread = Thread::new(program);
+    thread.set_fuel(Some(instruction_count - 1));
+    let error = thread.exec().unwrap_err();
+    assert_eq!(error.kind(), &RuntimeErrorKind::OutOfFuel);
+    assert_eq!(error.program_counter(), instruction_count as usize - 1);
+    thread.set_fuel(None);
+    assert_eq!(thread.resume(), Ok(Some(Value::Int(1))));
+}
+
+#[test]
+fn stepwise_execution() {
+    let mut compiler = Compiler::new();
+
+    let program = compiler
+        .compile("let x = 1;\nreturn x + 1;", "stdin")
+        .unwrap();
+    let mut thread = Thread::new(program);
+    let mut steps = 1;
+    let mut status = thread.step();
+    while status == Status::Running {
+        steps += 1;
+        status = thread.step();
+    }
+    assert_eq!(status, Status::Finished(Some(Value::Int(2))));
+    assert_eq!(steps, 4);
+    // A finished thread stays finished
+    assert_eq!(thread.step(), Status::Finished(Some(Value::Int(2))));
+    assert_eq!(thread.run_for(10), Status::Finished(Some(Value::Int(2))));
+
+    // Two threads interleaved on the same OS thread
+    let source = "let i = 0;\nlet sum = 0;\nwhile i < 50 {\nsum += i;\ni += 1;\n}\nreturn sum;";
+    let mut first = Thread::new(compiler.compile(source, "stdin").unwrap());
+    let mut second = Thread::new(compiler.compile(source, "stdin").unwrap());
+    let mut slices = 0;
+    let (first_result, second_result) = loop {
+        slices += 1;
+        match (first.run_for(10), second.run_for(10)) {
+            (Status::Running, Status::Running) => continue,
+            results => break results,
+        }
+    };
+    assert_eq!(first_result, Status::Finished(Some(Value::Int(1225))));
+    assert_eq!(second_result, Status::Finished(Some(Value::Int(1225))));
+    assert!(slices > 10);
+
+    let program = compiler
+        .compile("let xs = [1];\nlet i = 3;\nreturn xs[i];", "stdin")
+        .unwrap();
+    let mut thread = Thread::new(program);
+    match thread.run_for(100) {
+        Status::Error(error) => {
+            assert_eq!(error.kind(), &RuntimeErrorKind::IndexOutOfBounds(3, 1))
+        }
+        status => panic!("Expected an error, found {status:?}"),
+    }
+
+    // Zero steps only report the current state
+    let program = compiler.compile("return 1;", "stdin").unwrap();
+    let mut thread = Thread::new(program);
+    assert_eq!(thread.run_for(0), Status::Running);
+    assert_eq!(thread.run_for(100), Status::Finished(Some(Value::Int(1))));
+}
+
+#[test]
+fn debugger() {
+    let mut compiler = Compiler::new();
+    let source = "\
+fn square(n: int) -> int {
+    let result = n * n;
+    return result;
+}
+let total = 0;
+let i = 1;
+while i <= 3 {
+    total += square(i);
+    i += 1;
+}
+return total;";
+
+    // Paused before the first instruction, with nothing declared yet
+    let output = debug_and_capture_output(&mut compiler, source, "registers\nquit\n");
+    assert!(output.starts_with("Paused at the start of the program\n0    jump"));
+    assert!(output.contains("   1| fn square(n: int) -> int {\n"));
+    assert!(output.contains("No variables in scope\n"));
+
+    // Only the variables of the function's register window are shown inside of it
+    let output = debug_and_capture_output(&mut compiler, source, "b 3\nc\nr\nc\nr\n");
+    assert!(output.contains("Breakpoint set on line 3\n"));
+    assert!(output.contains("   3| return result;\n(debug) r0   n = 1\nr1   result = 1\n(debug) "));
+    assert!(output.contains("(debug) r0   n = 2\nr1   result = 4\n(debug) "));
+    assert!(!output.contains("total ="));
+
+    // Line breakpoints trigger once per visit of the line, also after the loop jumps back
+    let output = debug_and_capture_output(&mut compiler, source, "b 9\nc\nc\nr\nd 9\nc\n");
+    assert_eq!(output.matches("   9| i += 1;\n").count(), 2);
+    assert!(output.contains("r0   total = 5\nr1   i = 2\n"));
+    assert!(output.ends_with("Program finished\n14\n"));
+
+    // Instruction breakpoints and single steps
+    let output = debug_and_capture_output(&mut compiler, source, "b @2\nc\nstep 2\nw\n");
+    assert!(output.contains("(debug) 2    ret"));
+    assert!(output.contains("(debug) 11   ldnum"));
+    assert!(output.ends_with("   9| i += 1;\n(debug) \n"));
+
+    let output = debug_and_capture_output(
+        &mut compiler,
+        source,
+        "b 40\nb @999\nb x\nd 5\nstep 0\nfoo\n",
+    );
+    assert!(output.contains("There is no code on line 40\n"));
+    assert!(output.contains("There is no instruction 999, the program has 15\n"));
+    assert!(output.contains("Expected a line number or an instruction index like @12\n"));
+    assert!(output.contains("There is no such breakpoint\n"));
+    assert!(output.contains("Expected a positive number of steps\n"));
+    assert!(output.contains("Unknown command 'foo', enter 'help' for a list of commands\n"));
+
+    // Captured variables and runtime errors
+    let source = "\
+let xs = [1, 2];
+let get = fn(i: int) -> int {
+    return xs[i];
+};
+get(5);";
+    let output = debug_and_capture_output(&mut compiler, source, "b 3\nc\nr\nc\n");
+    assert!(output.contains("r0   i = 5\nr255 xs = [1, 2]\n"));
+    assert!(output.contains("Index 5 is out of bounds"));
+}